@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use fedimint_tonic_lnd::lnrpc::{ChannelEdge, Failure, HtlcAttempt, Invoice};
+use fedimint_tonic_lnd::routerrpc::{MissionControlConfig, PairHistory};
+use tokio::time::{sleep, Duration};
+
+use crate::channel_point::ChannelPoint;
+use crate::mpp::Split;
+use crate::records::{self, CustomRecords};
+use crate::setup::{ChannelOptions, ChannelRequest};
+use crate::Client;
+
+/// The node operations jammy's strategies and scenarios rely on, so they can run against a real
+/// LND node or against the in-process [`crate::sim::Simulator`].
+///
+/// The returned futures are not required to be `Send`: scenarios drive them on the caller's task.
+#[allow(async_fn_in_trait)]
+pub trait LightningBackend {
+    async fn get_pubkey(&mut self) -> String;
+
+    async fn graph_get_node_channels(&mut self, node_pubkey: String) -> Vec<ChannelEdge>;
+
+    async fn graph_get_node_peers(&mut self, node_pubkey: String) -> Vec<String>;
+
+    /// Connects to `node_pubkey` at one of the addresses it advertises, unless we already are
+    /// connected. Returns whether we are connected.
+    async fn connect_peer(&mut self, node_pubkey: String) -> bool;
+
+    /// Funding outpoints and capacities (sats) of our open and pending channels with
+    /// `node_pubkey`.
+    async fn channels_with(&mut self, node_pubkey: String) -> Vec<(ChannelPoint, i64)>;
+
+    /// Short channel id of an open channel.
+    async fn channel_id(&mut self, point: ChannelPoint) -> Option<u64>;
+
+    /// Opens a channel and returns its funding outpoint.
+    async fn open_channel(
+        &mut self,
+        node_pubkey: String,
+        local_funding_amount: i64,
+        push_sat: i64,
+        options: &ChannelOptions,
+    ) -> ChannelPoint;
+
+    /// Opens channels funded by a single transaction, returns their funding outpoints in order.
+    async fn batch_open_channels(
+        &mut self,
+        channels: &[&ChannelRequest],
+    ) -> Result<Vec<ChannelPoint>, String>;
+
+    /// Sets the forwarding policy of one of our open channels.
+    async fn update_channel_policy(
+        &mut self,
+        point: ChannelPoint,
+        base_fee_msat: i64,
+        fee_rate_ppm: u32,
+        time_lock_delta: u32,
+    );
+
+    /// Returns once none of our channels are pending open.
+    async fn wait_channels_confirmed(&mut self);
+
+    async fn add_hold_invoice(&mut self, hash: Vec<u8>, value: i64) -> String;
+
+    /// Sends an endorsed payment without waiting for its outcome.
+    async fn send_payment(&mut self, payment_request: String);
+
+    /// Like [`Self::send_payment`], carrying `custom_records` to the receiver and in shards if
+    /// `split` says so.
+    async fn send_payment_with(
+        &mut self,
+        payment_request: String,
+        split: Option<Split>,
+        custom_records: CustomRecords,
+    );
+
+    /// HTLC attempts of one of our payments so far.
+    async fn payment_htlcs(&mut self, payment_hash: [u8; 32]) -> Vec<HtlcAttempt>;
+
+    /// Sends `amount_msat` to a random payment hash along `hops`, the pubkeys of the nodes after
+    /// us, and returns the failure it comes back with. Errors if the probe could not be sent.
+    async fn probe_route(&mut self, hops: Vec<String>, amount_msat: i64)
+        -> Result<Failure, String>;
+
+    /// Starts holding the spontaneous payment of `hash`, see [`crate::spontaneous`]. Returns the
+    /// channel id the sender must route it over to us.
+    async fn expect_spontaneous(&mut self, hash: [u8; 32], preimage: [u8; 32]) -> u64;
+
+    /// Sends a keysend payment, or an AMP one if `amp`, of `amount` sats to be held by
+    /// `receiver`, without waiting for its outcome.
+    async fn send_spontaneous(
+        &mut self,
+        receiver: String,
+        chan_id: u64,
+        amount: i64,
+        preimage: [u8; 32],
+        amp: bool,
+        custom_records: CustomRecords,
+    );
+
+    /// A spontaneous payment we hold, presented like the invoice of a hold invoice payment.
+    async fn get_spontaneous(&mut self, hash: [u8; 32]) -> Invoice;
+
+    /// Settles or fails back a spontaneous payment we hold.
+    async fn resolve_spontaneous(&mut self, hash: [u8; 32], settle: bool);
+
+    async fn settle_invoice(&mut self, preimage: Vec<u8>);
+
+    async fn cancel_invoice(&mut self, payment_hash: Vec<u8>);
+
+    async fn get_invoice(&mut self, r_hash: Vec<u8>) -> Invoice;
+
+    /// Pair histories mission control has learned from past payments.
+    async fn query_mission_control(&mut self) -> Vec<PairHistory>;
+
+    async fn reset_mission_control(&mut self);
+
+    /// Adds pair histories to mission control, overriding newer ones with `force`.
+    async fn import_mission_control(&mut self, pairs: Vec<PairHistory>, force: bool);
+
+    async fn mission_control_config(&mut self) -> MissionControlConfig;
+
+    async fn set_mission_control_config(&mut self, config: MissionControlConfig);
+
+    /// Prints whether the htlcs paying the invoice were endorsed, and their custom records.
+    async fn lookup_invoice(&mut self, r_hash: Vec<u8>) {
+        for htlc in self.get_invoice(r_hash).await.htlcs {
+            if htlc.incoming_endorsed {
+                log!("HTLC endorsed!!");
+            } else {
+                log!("not endorsed");
+            }
+            if !htlc.custom_records.is_empty() {
+                log!("records {}", records::format(&htlc.custom_records));
+            }
+        }
+    }
+
+    async fn get_block_height(&mut self) -> u32;
+
+    /// Returns once `blocks` blocks have been mined.
+    async fn wait_blocks(&mut self, blocks: u32);
+
+    /// Confirmed on-chain balance, in sats.
+    async fn wallet_balance(&mut self) -> i64;
+
+    /// Amounts (sats) of the confirmed coins the wallet can spend.
+    async fn list_unspent(&mut self) -> Vec<i64>;
+
+    /// Fee rate in sat/vB expected to confirm within `conf_target` blocks.
+    async fn estimate_fee_rate(&mut self, conf_target: i32) -> u64;
+
+    async fn new_address(&mut self) -> String;
+
+    /// Pays every address its amount in sats in a single transaction.
+    async fn send_many(&mut self, outputs: HashMap<String, i64>, sat_per_vbyte: u64);
+}
+
+/// The node named `name` among `nodes`, which scenarios and strategies refer to by name.
+/// Panics if there is none.
+pub fn node<'a, B>(nodes: &'a mut HashMap<String, B>, name: &str) -> &'a mut B {
+    nodes
+        .get_mut(name)
+        .unwrap_or_else(|| panic!("unknown node {}", name))
+}
+
+impl LightningBackend for Client {
+    async fn get_pubkey(&mut self) -> String {
+        Client::get_pubkey(self).await
+    }
+
+    async fn graph_get_node_channels(&mut self, node_pubkey: String) -> Vec<ChannelEdge> {
+        Client::graph_get_node_channels(self, node_pubkey).await
+    }
+
+    async fn graph_get_node_peers(&mut self, node_pubkey: String) -> Vec<String> {
+        Client::graph_get_node_peers(self, node_pubkey).await
+    }
+
+    async fn connect_peer(&mut self, node_pubkey: String) -> bool {
+        // peers that advertise no address can still be connected, e.g. by connecting to us
+        if Client::is_connected(self, &node_pubkey).await {
+            return true;
+        }
+        for host in self.node_addresses(node_pubkey.clone()).await {
+            if Client::connect_peer(self, node_pubkey.clone(), host).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn channels_with(&mut self, node_pubkey: String) -> Vec<(ChannelPoint, i64)> {
+        Client::channels_with(self, node_pubkey).await
+    }
+
+    async fn channel_id(&mut self, point: ChannelPoint) -> Option<u64> {
+        Client::channel_id(self, point).await
+    }
+
+    async fn open_channel(
+        &mut self,
+        node_pubkey: String,
+        local_funding_amount: i64,
+        push_sat: i64,
+        options: &ChannelOptions,
+    ) -> ChannelPoint {
+        Client::open_channel(self, node_pubkey, local_funding_amount, push_sat, options).await
+    }
+
+    async fn batch_open_channels(
+        &mut self,
+        channels: &[&ChannelRequest],
+    ) -> Result<Vec<ChannelPoint>, String> {
+        Client::batch_open_channels(self, channels)
+            .await
+            .map_err(|status| String::from(status.message()))
+    }
+
+    async fn update_channel_policy(
+        &mut self,
+        point: ChannelPoint,
+        base_fee_msat: i64,
+        fee_rate_ppm: u32,
+        time_lock_delta: u32,
+    ) {
+        Client::update_channel_policy(self, point, base_fee_msat, fee_rate_ppm, time_lock_delta)
+            .await
+    }
+
+    async fn wait_channels_confirmed(&mut self) {
+        while self.pending_open_channels().await > 0 {
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn add_hold_invoice(&mut self, hash: Vec<u8>, value: i64) -> String {
+        Client::add_hold_invoice(self, hash, value).await
+    }
+
+    async fn send_payment(&mut self, payment_request: String) {
+        Client::send_payment(self, payment_request).await
+    }
+
+    async fn send_payment_with(
+        &mut self,
+        payment_request: String,
+        split: Option<Split>,
+        custom_records: CustomRecords,
+    ) {
+        Client::send_payment_with(self, payment_request, split, custom_records).await
+    }
+
+    async fn payment_htlcs(&mut self, payment_hash: [u8; 32]) -> Vec<HtlcAttempt> {
+        Client::payment_htlcs(self, payment_hash.to_vec()).await
+    }
+
+    async fn probe_route(
+        &mut self,
+        hops: Vec<String>,
+        amount_msat: i64,
+    ) -> Result<Failure, String> {
+        Client::probe_route(self, hops, amount_msat)
+            .await
+            .map_err(|status| String::from(status.message()))
+    }
+
+    async fn expect_spontaneous(&mut self, hash: [u8; 32], preimage: [u8; 32]) -> u64 {
+        self.holds().await.expect(hash, preimage)
+    }
+
+    async fn send_spontaneous(
+        &mut self,
+        receiver: String,
+        chan_id: u64,
+        amount: i64,
+        preimage: [u8; 32],
+        amp: bool,
+        custom_records: CustomRecords,
+    ) {
+        Client::send_spontaneous(
+            self,
+            receiver,
+            chan_id,
+            amount,
+            preimage,
+            amp,
+            custom_records,
+        )
+        .await
+    }
+
+    async fn get_spontaneous(&mut self, hash: [u8; 32]) -> Invoice {
+        self.holds().await.get(&hash)
+    }
+
+    async fn resolve_spontaneous(&mut self, hash: [u8; 32], settle: bool) {
+        self.holds().await.resolve(&hash, settle).await
+    }
+
+    async fn settle_invoice(&mut self, preimage: Vec<u8>) {
+        Client::settle_invoice(self, preimage).await
+    }
+
+    async fn cancel_invoice(&mut self, payment_hash: Vec<u8>) {
+        Client::cancel_invoice(self, payment_hash).await
+    }
+
+    async fn get_invoice(&mut self, r_hash: Vec<u8>) -> Invoice {
+        Client::get_invoice(self, r_hash).await
+    }
+
+    async fn query_mission_control(&mut self) -> Vec<PairHistory> {
+        Client::query_mission_control(self).await
+    }
+
+    async fn reset_mission_control(&mut self) {
+        Client::reset_mission_control(self).await
+    }
+
+    async fn import_mission_control(&mut self, pairs: Vec<PairHistory>, force: bool) {
+        Client::import_mission_control(self, pairs, force).await
+    }
+
+    async fn mission_control_config(&mut self) -> MissionControlConfig {
+        Client::mission_control_config(self).await
+    }
+
+    async fn set_mission_control_config(&mut self, config: MissionControlConfig) {
+        Client::set_mission_control_config(self, config).await
+    }
+
+    async fn get_block_height(&mut self) -> u32 {
+        Client::get_block_height(self).await
+    }
+
+    async fn wait_blocks(&mut self, blocks: u32) {
+        let start = Client::get_block_height(self).await;
+        while Client::get_block_height(self).await < start + blocks {
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn wallet_balance(&mut self) -> i64 {
+        Client::wallet_balance(self).await
+    }
+
+    async fn list_unspent(&mut self) -> Vec<i64> {
+        Client::list_unspent(self).await
+    }
+
+    async fn estimate_fee_rate(&mut self, conf_target: i32) -> u64 {
+        Client::estimate_fee_rate(self, conf_target).await
+    }
+
+    async fn new_address(&mut self) -> String {
+        Client::new_address(self).await
+    }
+
+    async fn send_many(&mut self, outputs: HashMap<String, i64>, sat_per_vbyte: u64) {
+        let txid = Client::send_many(self, outputs, sat_per_vbyte).await;
+        log!("funding transaction {}", txid);
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::channel_point::ChannelPoint;
+
+const HEADER: &str = "node,channel_point";
+
+/// Channels opened by our nodes, appended to a CSV file as they are opened so that
+/// `jammy teardown` can close them after the run. The file is kept across runs.
+pub struct ChannelLog {
+    out: File,
+}
+
+impl ChannelLog {
+    pub fn open(path: &str) -> Self {
+        let exists = Path::new(path).exists();
+        let mut out = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        if !exists {
+            writeln!(out, "{}", HEADER).unwrap();
+        }
+        ChannelLog { out }
+    }
+
+    pub fn record(&mut self, node: &str, point: &ChannelPoint) {
+        writeln!(self.out, "{},{}", node, point).unwrap();
+    }
+}
+
+/// Reads back the (node, channel point) entries of a channel log. A missing file has none.
+pub fn load(path: &str) -> Vec<(String, ChannelPoint)> {
+    let Ok(file) = File::open(path) else {
+        return Vec::new();
+    };
+    BufReader::new(file)
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let line = line.unwrap();
+            let (node, point) = line.split_once(',').unwrap();
+            (String::from(node), point.parse().unwrap())
+        })
+        .collect()
+}
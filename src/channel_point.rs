@@ -0,0 +1,128 @@
+use std::fmt;
+use std::str::FromStr;
+
+use fedimint_tonic_lnd::lnrpc;
+use fedimint_tonic_lnd::lnrpc::channel_point::FundingTxid;
+
+/// Funding outpoint of a channel. The txid is kept in display order, as printed by `lncli`,
+/// block explorers and the `channel_point` fields of `ListChannels` and the graph, while gRPC
+/// requests and responses carry its bytes reversed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChannelPoint {
+    pub txid: [u8; 32],
+    pub output_index: u32,
+}
+
+impl ChannelPoint {
+    pub fn from_rpc(point: &lnrpc::ChannelPoint) -> Self {
+        match point
+            .funding_txid
+            .as_ref()
+            .expect("channel point without txid")
+        {
+            FundingTxid::FundingTxidBytes(bytes) => {
+                ChannelPoint::from_rpc_txid(bytes, point.output_index)
+            }
+            FundingTxid::FundingTxidStr(txid) => ChannelPoint {
+                txid: hex::decode(txid)
+                    .expect("txid is not hex")
+                    .try_into()
+                    .expect("txid is not 32 bytes"),
+                output_index: point.output_index,
+            },
+        }
+    }
+
+    /// From txid bytes as carried by gRPC messages, e.g. `PendingUpdate`.
+    pub fn from_rpc_txid(txid: &[u8], output_index: u32) -> Self {
+        ChannelPoint {
+            txid: txid_from_rpc(txid),
+            output_index,
+        }
+    }
+
+    pub fn to_rpc(&self) -> lnrpc::ChannelPoint {
+        let mut txid = self.txid;
+        txid.reverse();
+        lnrpc::ChannelPoint {
+            funding_txid: Some(FundingTxid::FundingTxidBytes(txid.to_vec())),
+            output_index: self.output_index,
+        }
+    }
+}
+
+/// Txid bytes as carried by gRPC messages, e.g. a closing transaction's, in display order.
+pub fn txid_from_rpc(txid: &[u8]) -> [u8; 32] {
+    let mut txid: [u8; 32] = txid.try_into().expect("txid is not 32 bytes");
+    txid.reverse();
+    txid
+}
+
+impl fmt::Display for ChannelPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", hex::encode(self.txid), self.output_index)
+    }
+}
+
+impl FromStr for ChannelPoint {
+    type Err = String;
+
+    /// Parses the `txid:index` form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (txid, index) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid channel point {}", s))?;
+        let txid = hex::decode(txid)
+            .ok()
+            .and_then(|txid| txid.try_into().ok())
+            .ok_or_else(|| format!("invalid txid in {}", s))?;
+        let output_index = index
+            .parse()
+            .map_err(|_| format!("invalid output index in {}", s))?;
+        Ok(ChannelPoint { txid, output_index })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_txid_is_reversed() {
+        let rpc: Vec<u8> = (0..32).collect();
+        let point = ChannelPoint::from_rpc_txid(&rpc, 1);
+        assert_eq!(point.txid[0], 31);
+        assert_eq!(point.txid[31], 0);
+        assert_eq!(hex::encode(txid_from_rpc(&rpc)), hex::encode(point.txid));
+        assert_eq!(ChannelPoint::from_rpc(&point.to_rpc()), point);
+        match point.to_rpc().funding_txid {
+            Some(FundingTxid::FundingTxidBytes(bytes)) => assert_eq!(bytes, rpc),
+            _ => panic!("expected txid bytes"),
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let point = ChannelPoint {
+            txid: [0xab; 32],
+            output_index: 7,
+        };
+        let shown = point.to_string();
+        assert_eq!(shown, format!("{}:7", "ab".repeat(32)));
+        assert_eq!(shown.parse(), Ok(point));
+    }
+
+    #[test]
+    fn malformed_points_are_rejected() {
+        let txid = "ab".repeat(32);
+        for invalid in [
+            String::from("abc"),
+            String::from("txid:x"),
+            format!("{}:x", txid),
+            format!("{}:-1", txid),
+            format!("{}:0", &txid[2..]),
+        ] {
+            assert!(invalid.parse::<ChannelPoint>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,869 @@
+use crate::channel_point::ChannelPoint;
+use crate::events::{self, Event};
+use crate::failures::HopFailure;
+use crate::gen_hash_table;
+use crate::metrics;
+use crate::mpp::Split;
+use crate::records::CustomRecords;
+use crate::setup::{ChannelOptions, ChannelRequest};
+use crate::spontaneous::{Holds, HOLD_DESTINATION, KEYSEND_RECORD};
+
+/// Thin wrapper around an LND connection exposing the calls jammy needs. Calls panic on RPC
+/// errors. Clones share the connection and the spontaneous payments held for the node.
+#[derive(Clone)]
+pub struct Client(
+    fedimint_tonic_lnd::Client,
+    std::sync::Arc<tokio::sync::OnceCell<Holds>>,
+);
+
+impl Client {
+    /// Connects to the gRPC interface of the LND node at `host`, on the default port.
+    pub async fn connect(host: &str, cert_file: &str, macaroon_file: &str) -> Self {
+        Client(
+            fedimint_tonic_lnd::connect(
+                format!("https://{}:10009", host),
+                cert_file,
+                macaroon_file,
+            )
+            .await
+            .unwrap(),
+            Default::default(),
+        )
+    }
+
+    /// Wraps an existing connection.
+    pub fn new(client: fedimint_tonic_lnd::Client) -> Self {
+        Client(client, Default::default())
+    }
+
+    pub async fn get_pubkey(&mut self) -> String {
+        self.0
+            .lightning()
+            .get_info(fedimint_tonic_lnd::lnrpc::GetInfoRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .identity_pubkey
+    }
+
+    pub async fn get_block_height(&mut self) -> u32 {
+        self.0
+            .lightning()
+            .get_info(fedimint_tonic_lnd::lnrpc::GetInfoRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .block_height
+    }
+
+    pub async fn graph_get_node_channels(
+        &mut self,
+        node_pubkey: String,
+    ) -> Vec<fedimint_tonic_lnd::lnrpc::ChannelEdge> {
+        self.0
+            .lightning()
+            .get_node_info(fedimint_tonic_lnd::lnrpc::NodeInfoRequest {
+                pub_key: node_pubkey,
+                include_channels: true,
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .channels
+    }
+
+    pub async fn graph_get_node_peers(&mut self, node_pubkey: String) -> Vec<String> {
+        let channels = self.graph_get_node_channels(node_pubkey.clone()).await;
+        channels
+            .iter()
+            .map(|channel| {
+                if channel.node1_pub == node_pubkey {
+                    channel.node2_pub.clone()
+                } else {
+                    channel.node1_pub.clone()
+                }
+            })
+            .collect()
+    }
+
+    pub async fn subscribe_channel_graph(
+        &mut self,
+    ) -> fedimint_tonic_lnd::tonic::Streaming<fedimint_tonic_lnd::lnrpc::GraphTopologyUpdate> {
+        self.0
+            .lightning()
+            .subscribe_channel_graph(fedimint_tonic_lnd::lnrpc::GraphTopologySubscription {})
+            .await
+            .expect("Failed to call subscribe_channel_graph")
+            .into_inner()
+    }
+
+    pub async fn pending_open_channels(&mut self) -> usize {
+        self.list_pending_open_channels().await.len()
+    }
+
+    pub async fn list_pending_open_channels(
+        &mut self,
+    ) -> Vec<fedimint_tonic_lnd::lnrpc::pending_channels_response::PendingChannel> {
+        self.0
+            .lightning()
+            .pending_channels(fedimint_tonic_lnd::lnrpc::PendingChannelsRequest::default())
+            .await
+            .unwrap()
+            .into_inner()
+            .pending_open_channels
+            .into_iter()
+            .filter_map(|pending| pending.channel)
+            .collect()
+    }
+
+    /// Funding outpoints and capacities (sats) of our open and pending channels with
+    /// `node_pubkey`.
+    pub async fn channels_with(&mut self, node_pubkey: String) -> Vec<(ChannelPoint, i64)> {
+        let mut channels: Vec<(ChannelPoint, i64)> = self
+            .list_channels()
+            .await
+            .iter()
+            .filter(|channel| channel.remote_pubkey == node_pubkey)
+            .map(|channel| (channel.channel_point.parse().unwrap(), channel.capacity))
+            .collect();
+        channels.extend(
+            self.list_pending_open_channels()
+                .await
+                .iter()
+                .filter(|channel| channel.remote_node_pub == node_pubkey)
+                .map(|channel| (channel.channel_point.parse().unwrap(), channel.capacity)),
+        );
+        channels
+    }
+
+    /// Short channel id of an open channel, as used by the graph and HTLC events.
+    pub async fn channel_id(&mut self, point: ChannelPoint) -> Option<u64> {
+        self.list_channels()
+            .await
+            .iter()
+            .find(|channel| channel.channel_point.parse() == Ok(point))
+            .map(|channel| channel.chan_id)
+    }
+
+    pub async fn subscribe_htlc_events(
+        &mut self,
+    ) -> fedimint_tonic_lnd::tonic::Streaming<fedimint_tonic_lnd::routerrpc::HtlcEvent> {
+        self.0
+            .router()
+            .subscribe_htlc_events(fedimint_tonic_lnd::routerrpc::SubscribeHtlcEventsRequest {})
+            .await
+            .expect("Failed to call subscribe_htlc_events")
+            .into_inner()
+    }
+
+    /// Registers as the node's HTLC interceptor. Every intercepted HTLC must be answered on the
+    /// returned sender.
+    pub async fn htlc_interceptor(
+        &mut self,
+    ) -> (
+        tokio::sync::mpsc::Sender<fedimint_tonic_lnd::routerrpc::ForwardHtlcInterceptResponse>,
+        fedimint_tonic_lnd::tonic::Streaming<
+            fedimint_tonic_lnd::routerrpc::ForwardHtlcInterceptRequest,
+        >,
+    ) {
+        let (tx, rx) = tokio::sync::mpsc::channel(1024);
+        let stream = self
+            .0
+            .router()
+            .htlc_interceptor(tokio_stream::wrappers::ReceiverStream::new(rx))
+            .await
+            .expect("Failed to call htlc_interceptor")
+            .into_inner();
+        (tx, stream)
+    }
+
+    pub async fn list_channels(&mut self) -> Vec<fedimint_tonic_lnd::lnrpc::Channel> {
+        self.0
+            .lightning()
+            .list_channels(fedimint_tonic_lnd::lnrpc::ListChannelsRequest {
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .channels
+    }
+
+    /// Opens a channel and returns its funding outpoint once the funding transaction is
+    /// published.
+    pub async fn open_channel(
+        &mut self,
+        node_pubkey: String,
+        local_funding_amount: i64,
+        push_sat: i64,
+        options: &ChannelOptions,
+    ) -> ChannelPoint {
+        let res = self
+            .0
+            .lightning()
+            .open_channel_sync(fedimint_tonic_lnd::lnrpc::OpenChannelRequest {
+                node_pubkey: hex::decode(&node_pubkey).unwrap(),
+                local_funding_amount,
+                push_sat,
+                private: options.private,
+                commitment_type: options.commitment_type.map_or(0, |commitment_type| {
+                    fedimint_tonic_lnd::lnrpc::CommitmentType::from(commitment_type) as i32
+                }),
+                zero_conf: options.zero_conf,
+                scid_alias: options.scid_alias,
+                sat_per_vbyte: options.sat_per_vbyte.unwrap_or_default(),
+                min_htlc_msat: options.min_htlc_msat.unwrap_or_default(),
+                remote_max_htlcs: options.remote_max_htlcs.unwrap_or_default(),
+                max_local_csv: options.max_local_csv.unwrap_or_default(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let point = ChannelPoint::from_rpc(&res);
+        log!("{}", point);
+        point
+    }
+
+    /// Opens channels to several peers funded by a single transaction, returns their funding
+    /// outpoints in order. The fee rate is the highest one requested.
+    pub async fn batch_open_channels(
+        &mut self,
+        channels: &[&ChannelRequest],
+    ) -> Result<Vec<ChannelPoint>, fedimint_tonic_lnd::tonic::Status> {
+        let sat_per_vbyte = channels
+            .iter()
+            .filter_map(|channel| channel.options.sat_per_vbyte)
+            .max()
+            .unwrap_or_default();
+        let res = self
+            .0
+            .lightning()
+            .batch_open_channel(fedimint_tonic_lnd::lnrpc::BatchOpenChannelRequest {
+                channels: channels
+                    .iter()
+                    .map(|channel| fedimint_tonic_lnd::lnrpc::BatchOpenChannel {
+                        node_pubkey: hex::decode(&channel.peer).unwrap(),
+                        local_funding_amount: channel.amount,
+                        push_sat: channel.push,
+                        private: channel.options.private,
+                        min_htlc_msat: channel.options.min_htlc_msat.unwrap_or_default(),
+                        commitment_type: channel.options.commitment_type.map_or(
+                            0,
+                            |commitment_type| {
+                                fedimint_tonic_lnd::lnrpc::CommitmentType::from(commitment_type)
+                                    as i32
+                            },
+                        ),
+                        ..Default::default()
+                    })
+                    .collect(),
+                sat_per_vbyte: sat_per_vbyte as i64,
+                label: String::from("jammy"),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let points: Vec<ChannelPoint> = res
+            .pending_channels
+            .iter()
+            .map(|pending| ChannelPoint::from_rpc_txid(&pending.txid, pending.output_index))
+            .collect();
+        for point in &points {
+            log!("{}", point);
+        }
+        Ok(points)
+    }
+
+    /// Sets the forwarding policy of one of our channels.
+    pub async fn update_channel_policy(
+        &mut self,
+        point: ChannelPoint,
+        base_fee_msat: i64,
+        fee_rate_ppm: u32,
+        time_lock_delta: u32,
+    ) {
+        use fedimint_tonic_lnd::lnrpc::policy_update_request::Scope;
+        let res = self
+            .0
+            .lightning()
+            .update_channel_policy(fedimint_tonic_lnd::lnrpc::PolicyUpdateRequest {
+                scope: Some(Scope::ChanPoint(point.to_rpc())),
+                base_fee_msat,
+                fee_rate_ppm,
+                time_lock_delta,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        for failed in res.failed_updates {
+            log!("policy update of {} failed: {}", point, failed.update_error);
+        }
+    }
+
+    /// Closes a channel, cooperatively at `sat_per_vbyte` unless `force`. The returned stream
+    /// ends with the confirmation of the closing transaction.
+    pub async fn close_channel(
+        &mut self,
+        channel_point: ChannelPoint,
+        sat_per_vbyte: u64,
+        force: bool,
+    ) -> Result<
+        fedimint_tonic_lnd::tonic::Streaming<fedimint_tonic_lnd::lnrpc::CloseStatusUpdate>,
+        fedimint_tonic_lnd::tonic::Status,
+    > {
+        self.0
+            .lightning()
+            .close_channel(fedimint_tonic_lnd::lnrpc::CloseChannelRequest {
+                channel_point: Some(channel_point.to_rpc()),
+                force,
+                // force closes use the fee rate of the commitment transaction
+                sat_per_vbyte: if force { 0 } else { sat_per_vbyte },
+                ..Default::default()
+            })
+            .await
+            .map(|response| response.into_inner())
+    }
+
+    pub async fn add_hold_invoice(&mut self, hash: Vec<u8>, value: i64) -> String {
+        self.0
+            .invoices()
+            .add_hold_invoice(fedimint_tonic_lnd::invoicesrpc::AddHoldInvoiceRequest {
+                hash,
+                value,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .payment_request
+    }
+
+    pub async fn add_invoice(&mut self, value: i64) -> (String, Vec<u8>) {
+        let res = self
+            .0
+            .lightning()
+            .add_invoice(fedimint_tonic_lnd::lnrpc::Invoice {
+                value,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        (res.payment_request, res.r_hash)
+    }
+
+    /// Pays an invoice and waits for the payment to reach a final state, which is returned.
+    /// A non-empty `last_hop_pubkey` forces the route through that node. Fails if lnd rejects
+    /// the payment or the status stream breaks before it is resolved.
+    pub async fn pay_invoice(
+        &mut self,
+        payment_request: String,
+        last_hop_pubkey: Vec<u8>,
+        endorsed: bool,
+        timeout_seconds: i32,
+    ) -> Result<fedimint_tonic_lnd::lnrpc::Payment, fedimint_tonic_lnd::tonic::Status> {
+        use fedimint_tonic_lnd::lnrpc::payment::PaymentStatus;
+        let mut stream = self
+            .0
+            .router()
+            .send_payment_v2(fedimint_tonic_lnd::routerrpc::SendPaymentRequest {
+                payment_request,
+                fee_limit_sat: 100_000,
+                timeout_seconds,
+                last_hop_pubkey,
+                endorsed: endorsed as i32,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let mut last = fedimint_tonic_lnd::lnrpc::Payment::default();
+        while let Some(payment) = stream.message().await? {
+            metrics::record_payment(&payment, metrics::PaymentKind::Honest);
+            events::publish(Event::Payment(payment.clone()));
+            let done = payment.status == PaymentStatus::Succeeded as i32
+                || payment.status == PaymentStatus::Failed as i32;
+            last = payment;
+            if done {
+                break;
+            }
+        }
+        Ok(last)
+    }
+
+    /// Sends `amount_msat` to a random payment hash along `hops`, pubkeys of the nodes after us
+    /// in order, and returns the failure it comes back with.
+    pub async fn probe_route(
+        &mut self,
+        hops: Vec<String>,
+        amount_msat: i64,
+    ) -> Result<fedimint_tonic_lnd::lnrpc::Failure, fedimint_tonic_lnd::tonic::Status> {
+        let route = self
+            .0
+            .router()
+            .build_route(fedimint_tonic_lnd::routerrpc::BuildRouteRequest {
+                amt_msat: amount_msat,
+                final_cltv_delta: 40,
+                hop_pubkeys: hops.iter().map(|hop| hex::decode(hop).unwrap()).collect(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .route;
+        let (_, hash) = gen_hash_table(1)[0];
+        let attempt = self
+            .0
+            .router()
+            .send_to_route_v2(fedimint_tonic_lnd::routerrpc::SendToRouteRequest {
+                payment_hash: hash.to_vec(),
+                route,
+                skip_temp_err: false,
+            })
+            .await?
+            .into_inner();
+        attempt.failure.ok_or_else(|| {
+            fedimint_tonic_lnd::tonic::Status::internal("probe to a random hash succeeded")
+        })
+    }
+
+    pub async fn send_payment(&mut self, payment_request: String) {
+        self.send_payment_with(payment_request, None, CustomRecords::new())
+            .await
+    }
+
+    /// Sends an endorsed payment carrying `custom_records` to its receiver, in shards if `split`
+    /// says so, without waiting for its outcome.
+    pub async fn send_payment_with(
+        &mut self,
+        payment_request: String,
+        split: Option<Split>,
+        custom_records: CustomRecords,
+    ) {
+        let mut request = fedimint_tonic_lnd::routerrpc::SendPaymentRequest {
+            payment_request,
+            fee_limit_sat: 100_000,
+            timeout_seconds: 100_000,
+            endorsed: 1i32,
+            allow_self_payment: true,
+            dest_custom_records: custom_records,
+            ..Default::default()
+        };
+        if let Some(split) = split {
+            request.max_parts = split.max_parts;
+            request.max_shard_size_msat = split.max_shard_size_msat;
+        }
+        self.spawn_payment(request).await
+    }
+
+    /// Sends a payment and follows its updates in the background.
+    async fn spawn_payment(&mut self, request: fedimint_tonic_lnd::routerrpc::SendPaymentRequest) {
+        let mut stream = self
+            .0
+            .router()
+            .send_payment_v2(request)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut client = self.clone();
+        tokio::task::spawn(async move {
+            let mut failed_attempts = std::collections::HashSet::new();
+            while let Some(payment) = stream.message().await.unwrap() {
+                metrics::record_payment(&payment, metrics::PaymentKind::Jam);
+                events::publish(Event::Payment(payment.clone()));
+                for attempt in &payment.htlcs {
+                    if attempt.status
+                        != fedimint_tonic_lnd::lnrpc::htlc_attempt::HtlcStatus::Failed as i32
+                        || !failed_attempts.insert(attempt.attempt_id)
+                    {
+                        continue;
+                    }
+                    let height = client.get_block_height().await;
+                    if let Some(failure) = HopFailure::decode(attempt, height) {
+                        log!(
+                            "payment {} attempt {} failed: {}",
+                            payment.payment_hash,
+                            attempt.attempt_id,
+                            failure
+                        );
+                    }
+                }
+                if payment.status == 3 {
+                    log!("payment failed!");
+                } else if payment.status == 2 {
+                    log!("payment success!");
+                }
+            }
+        });
+    }
+
+    /// HTLC attempts of one of our payments so far, none if we don't know the payment.
+    pub async fn payment_htlcs(
+        &mut self,
+        payment_hash: Vec<u8>,
+    ) -> Vec<fedimint_tonic_lnd::lnrpc::HtlcAttempt> {
+        let Ok(stream) = self
+            .0
+            .router()
+            .track_payment_v2(fedimint_tonic_lnd::routerrpc::TrackPaymentRequest {
+                payment_hash,
+                no_inflight_updates: false,
+            })
+            .await
+        else {
+            return Vec::new();
+        };
+        let mut stream = stream.into_inner();
+        stream
+            .message()
+            .await
+            .ok()
+            .flatten()
+            .map(|payment| payment.htlcs)
+            .unwrap_or_default()
+    }
+
+    /// Sends a keysend payment, or an AMP one if `amp`, to be held by `receiver` over `chan_id`,
+    /// see [`crate::spontaneous`]. Does not wait for its outcome.
+    pub async fn send_spontaneous(
+        &mut self,
+        receiver: String,
+        chan_id: u64,
+        amount: i64,
+        preimage: [u8; 32],
+        amp: bool,
+        custom_records: CustomRecords,
+    ) {
+        use fedimint_tonic_lnd::lnrpc::FeatureBit;
+        let route_hints = vec![fedimint_tonic_lnd::lnrpc::RouteHint {
+            hop_hints: vec![fedimint_tonic_lnd::lnrpc::HopHint {
+                node_id: receiver,
+                chan_id,
+                cltv_expiry_delta: 40,
+                ..Default::default()
+            }],
+        }];
+        let mut dest_features = vec![FeatureBit::TlvOnionOpt as i32];
+        let mut request = fedimint_tonic_lnd::routerrpc::SendPaymentRequest {
+            dest: hex::decode(HOLD_DESTINATION).unwrap(),
+            amt: amount,
+            fee_limit_sat: 100_000,
+            timeout_seconds: 100_000,
+            route_hints,
+            endorsed: 1i32,
+            dest_custom_records: custom_records,
+            ..Default::default()
+        };
+        if amp {
+            dest_features.extend([FeatureBit::PaymentAddrOpt as i32, FeatureBit::AmpOpt as i32]);
+            request.amp = true;
+            request.payment_addr = gen_hash_table(1)[0].0.to_vec();
+        } else {
+            use bitcoin_hashes::Hash;
+            let hash = bitcoin_hashes::sha256::Hash::hash(&preimage).to_byte_array();
+            request.payment_hash = hash.to_vec();
+            request
+                .dest_custom_records
+                .insert(KEYSEND_RECORD, preimage.to_vec());
+        }
+        request.dest_features = dest_features;
+        self.spawn_payment(request).await
+    }
+
+    /// Spontaneous payments held for this node, registering its HTLC interceptor on first use.
+    pub async fn holds(&mut self) -> Holds {
+        let mut client = self.clone();
+        self.1
+            .get_or_init(|| async move { Holds::start(&mut client).await })
+            .await
+            .clone()
+    }
+
+    pub async fn settle_invoice(&mut self, preimage: Vec<u8>) {
+        let _res = self
+            .0
+            .invoices()
+            .settle_invoice(fedimint_tonic_lnd::invoicesrpc::SettleInvoiceMsg { preimage })
+            .await
+            .unwrap()
+            .into_inner();
+        //log!("{:?}", res);
+    }
+
+    /// Subscribes to invoice updates, replaying invoices added after `add_index` and settled
+    /// after `settle_index`. Errors are returned so callers can reconnect.
+    pub async fn subscribe_invoices(
+        &mut self,
+        add_index: u64,
+        settle_index: u64,
+    ) -> Result<
+        fedimint_tonic_lnd::tonic::Streaming<fedimint_tonic_lnd::lnrpc::Invoice>,
+        fedimint_tonic_lnd::tonic::Status,
+    > {
+        self.0
+            .lightning()
+            .subscribe_invoices(fedimint_tonic_lnd::lnrpc::InvoiceSubscription {
+                add_index,
+                settle_index,
+            })
+            .await
+            .map(|response| response.into_inner())
+    }
+
+    pub async fn get_invoice(&mut self, r_hash: Vec<u8>) -> fedimint_tonic_lnd::lnrpc::Invoice {
+        let invoice = self
+            .0
+            .lightning()
+            .lookup_invoice(fedimint_tonic_lnd::lnrpc::PaymentHash {
+                r_hash,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        metrics::record_invoice(&invoice);
+        invoice
+    }
+
+    /// Whether we are connected to `pubkey`.
+    pub async fn is_connected(&mut self, pubkey: &str) -> bool {
+        self.0
+            .lightning()
+            .list_peers(fedimint_tonic_lnd::lnrpc::ListPeersRequest::default())
+            .await
+            .unwrap()
+            .into_inner()
+            .peers
+            .iter()
+            .any(|peer| peer.pub_key == pubkey)
+    }
+
+    /// Connects to the peer at `host`. Returns whether we are connected, including when we
+    /// already were.
+    pub async fn connect_peer(&mut self, pubkey: String, host: String) -> bool {
+        use fedimint_tonic_lnd::lnrpc::LightningAddress;
+        match self
+            .0
+            .lightning()
+            .connect_peer(fedimint_tonic_lnd::lnrpc::ConnectPeerRequest {
+                addr: Some(LightningAddress { pubkey, host }),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(_) => true,
+            Err(status) => status.message().contains("already connected"),
+        }
+    }
+
+    /// Addresses `node_pubkey` advertises in the graph.
+    pub async fn node_addresses(&mut self, node_pubkey: String) -> Vec<String> {
+        self.0
+            .lightning()
+            .get_node_info(fedimint_tonic_lnd::lnrpc::NodeInfoRequest {
+                pub_key: node_pubkey,
+                include_channels: false,
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .node
+            .map_or(Vec::new(), |node| {
+                node.addresses
+                    .into_iter()
+                    .map(|address| address.addr)
+                    .collect()
+            })
+    }
+
+    pub async fn new_address(&mut self) -> String {
+        self.0
+            .lightning()
+            .new_address(fedimint_tonic_lnd::lnrpc::NewAddressRequest {
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .address
+    }
+
+    /// Confirmed on-chain balance of the node's wallet, in sats.
+    pub async fn wallet_balance(&mut self) -> i64 {
+        self.0
+            .lightning()
+            .wallet_balance(fedimint_tonic_lnd::lnrpc::WalletBalanceRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .confirmed_balance
+    }
+
+    /// Amounts (sats) of the wallet's confirmed and unlocked UTXOs.
+    pub async fn list_unspent(&mut self) -> Vec<i64> {
+        self.0
+            .wallet()
+            .list_unspent(fedimint_tonic_lnd::walletrpc::ListUnspentRequest {
+                min_confs: 1,
+                max_confs: i32::MAX,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .utxos
+            .iter()
+            .map(|utxo| utxo.amount_sat)
+            .collect()
+    }
+
+    /// Fee rate in sat/vB expected to confirm a transaction within `conf_target` blocks.
+    pub async fn estimate_fee_rate(&mut self, conf_target: i32) -> u64 {
+        let sat_per_kw = self
+            .0
+            .wallet()
+            .estimate_fee(fedimint_tonic_lnd::walletrpc::EstimateFeeRequest { conf_target })
+            .await
+            .unwrap()
+            .into_inner()
+            .sat_per_kw;
+        // 1 kw is 250 vbytes
+        (sat_per_kw as u64).div_ceil(250).max(1)
+    }
+
+    /// Sends the whole confirmed wallet balance to `addr`, returns the txid.
+    pub async fn sweep(&mut self, addr: String, sat_per_vbyte: u64) -> String {
+        self.0
+            .lightning()
+            .send_coins(fedimint_tonic_lnd::lnrpc::SendCoinsRequest {
+                addr,
+                send_all: true,
+                sat_per_vbyte,
+                min_confs: 1,
+                label: String::from("jammy sweep"),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .txid
+    }
+
+    /// Pays every address its amount in sats, with `SendCoins` for a single address and
+    /// `SendMany` otherwise. Only confirmed coins are spent. Returns the txid.
+    pub async fn send_many(
+        &mut self,
+        outputs: std::collections::HashMap<String, i64>,
+        sat_per_vbyte: u64,
+    ) -> String {
+        if outputs.len() == 1 {
+            let (addr, amount) = outputs.into_iter().next().unwrap();
+            return self
+                .0
+                .lightning()
+                .send_coins(fedimint_tonic_lnd::lnrpc::SendCoinsRequest {
+                    addr,
+                    amount,
+                    sat_per_vbyte,
+                    min_confs: 1,
+                    label: String::from("jammy"),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner()
+                .txid;
+        }
+        self.0
+            .lightning()
+            .send_many(fedimint_tonic_lnd::lnrpc::SendManyRequest {
+                addr_to_amount: outputs,
+                sat_per_vbyte,
+                min_confs: 1,
+                label: String::from("jammy"),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .txid
+    }
+
+    pub async fn cancel_invoice(&mut self, payment_hash: Vec<u8>) {
+        let _res = self
+            .0
+            .invoices()
+            .cancel_invoice(fedimint_tonic_lnd::invoicesrpc::CancelInvoiceMsg { payment_hash })
+            .await
+            .unwrap()
+            .into_inner();
+        //log!("{:?}", res);
+    }
+
+    /// Pair histories mission control has learned from past payments.
+    pub async fn query_mission_control(
+        &mut self,
+    ) -> Vec<fedimint_tonic_lnd::routerrpc::PairHistory> {
+        self.0
+            .router()
+            .query_mission_control(fedimint_tonic_lnd::routerrpc::QueryMissionControlRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .pairs
+    }
+
+    pub async fn reset_mission_control(&mut self) {
+        self.0
+            .router()
+            .reset_mission_control(fedimint_tonic_lnd::routerrpc::ResetMissionControlRequest {})
+            .await
+            .unwrap();
+    }
+
+    /// Adds pair histories to mission control. Without `force`, results older than what
+    /// mission control already knows of a pair are ignored.
+    pub async fn import_mission_control(
+        &mut self,
+        pairs: Vec<fedimint_tonic_lnd::routerrpc::PairHistory>,
+        force: bool,
+    ) {
+        self.0
+            .router()
+            .x_import_mission_control(
+                fedimint_tonic_lnd::routerrpc::XImportMissionControlRequest { pairs, force },
+            )
+            .await
+            .unwrap();
+    }
+
+    pub async fn mission_control_config(
+        &mut self,
+    ) -> fedimint_tonic_lnd::routerrpc::MissionControlConfig {
+        self.0
+            .router()
+            .get_mission_control_config(
+                fedimint_tonic_lnd::routerrpc::GetMissionControlConfigRequest {},
+            )
+            .await
+            .unwrap()
+            .into_inner()
+            .config
+            .unwrap_or_default()
+    }
+
+    pub async fn set_mission_control_config(
+        &mut self,
+        config: fedimint_tonic_lnd::routerrpc::MissionControlConfig,
+    ) {
+        self.0
+            .router()
+            .set_mission_control_config(
+                fedimint_tonic_lnd::routerrpc::SetMissionControlConfigRequest {
+                    config: Some(config),
+                },
+            )
+            .await
+            .unwrap();
+    }
+}
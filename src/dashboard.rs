@@ -0,0 +1,350 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{stderr, Stderr};
+use std::sync::{Arc, Mutex};
+
+use fedimint_tonic_lnd::lnrpc::htlc_attempt::HtlcStatus;
+use fedimint_tonic_lnd::lnrpc::payment::PaymentStatus;
+use fedimint_tonic_lnd::lnrpc::{ChannelEdge, Hop, Payment, PaymentFailureReason};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self as term, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::time::{interval, Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::invoice_feed::InvoiceEvent;
+use crate::strategy::{JamHtlc, CONTROLS};
+use crate::Client;
+
+/// LND's default `max_accepted_htlcs`, used as the size of the slot bars.
+const MAX_HTLC_SLOTS: usize = 483;
+const LOG_LINES: usize = 200;
+const BAR_WIDTH: usize = 20;
+/// How long quitting waits for the run to release what it holds.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Dashboard {
+    target: String,
+    channels: Arc<Mutex<Vec<ChannelEdge>>>,
+    /// Our in-flight HTLCs on target channels as (chan_id, amount msat), by payment hash.
+    in_flight: HashMap<String, Vec<(u64, i64)>>,
+    /// Active payments of the running strategy.
+    jams: BTreeMap<[u8; 32], JamHtlc>,
+    height: u32,
+    fees_msat: i64,
+    /// Most recent first.
+    log: VecDeque<String>,
+}
+
+/// Amount of the HTLC sent over the hop's channel.
+fn htlc_amount(hop: &Hop) -> i64 {
+    hop.amt_to_forward_msat + hop.fee_msat
+}
+
+fn bar(used: f64) -> String {
+    let filled = ((used.clamp(0.0, 1.0) * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+    format!("[{}{}]", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled))
+}
+
+impl Dashboard {
+    fn push_log(&mut self, line: String) {
+        self.log.push_front(line);
+        self.log.truncate(LOG_LINES);
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Payment(payment) => self.apply_payment(payment),
+            Event::Jam(htlc) => {
+                self.push_log(format!(
+                    "jam {} {} -> {} {} sat {}",
+                    &hex::encode(htlc.hash)[..8],
+                    htlc.sender,
+                    htlc.receiver,
+                    htlc.amount,
+                    htlc.state.name()
+                ));
+                if htlc.state.is_active() {
+                    self.jams.insert(htlc.hash, htlc);
+                } else {
+                    self.jams.remove(&htlc.hash);
+                }
+            }
+            Event::Height(height) => self.height = height,
+            Event::Invoice(InvoiceEvent::HtlcAccepted {
+                node,
+                hash,
+                amount_msat,
+                incoming_endorsed,
+                ..
+            }) => self.push_log(format!(
+                "htlc {} at {} {} sat {}",
+                hex::encode(&hash[..hash.len().min(4)]),
+                node,
+                amount_msat / 1000,
+                if incoming_endorsed {
+                    "endorsed"
+                } else {
+                    "unendorsed"
+                }
+            )),
+            Event::Invoice(_) => {}
+            Event::Log(line) => self.push_log(line),
+        }
+    }
+
+    fn apply_payment(&mut self, payment: Payment) {
+        if payment.status == PaymentStatus::Succeeded as i32
+            || payment.status == PaymentStatus::Failed as i32
+        {
+            self.in_flight.remove(&payment.payment_hash);
+            if payment.status == PaymentStatus::Succeeded as i32 {
+                self.fees_msat += payment.fee_msat;
+            }
+            self.push_log(format!(
+                "payment {} {} sat {} {}",
+                payment.payment_hash.get(..8).unwrap_or_default(),
+                payment.value_sat,
+                PaymentStatus::try_from(payment.status)
+                    .map_or("UNKNOWN", |status| status.as_str_name()),
+                PaymentFailureReason::try_from(payment.failure_reason)
+                    .map_or("UNKNOWN", |reason| reason.as_str_name()),
+            ));
+            return;
+        }
+
+        let mut htlcs = Vec::new();
+        for attempt in &payment.htlcs {
+            if attempt.status != HtlcStatus::InFlight as i32 {
+                continue;
+            }
+            let hops = attempt
+                .route
+                .as_ref()
+                .map_or(&[][..], |route| &route.hops[..]);
+            for (i, hop) in hops.iter().enumerate() {
+                if hop.pub_key == self.target {
+                    htlcs.push((hop.chan_id, htlc_amount(hop)));
+                    if let Some(next) = hops.get(i + 1) {
+                        htlcs.push((next.chan_id, htlc_amount(next)));
+                    }
+                }
+            }
+        }
+        self.in_flight.insert(payment.payment_hash, htlcs);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let channels = self.channels.lock().unwrap().clone();
+        let [channels_area, jams_area, log_area, status_area] = Layout::vertical([
+            Constraint::Length(channels.len() as u16 + 3),
+            Constraint::Min(6),
+            Constraint::Length(10),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let mut usage: HashMap<u64, (usize, i64)> = HashMap::new();
+        for (chan_id, amount_msat) in self.in_flight.values().flatten() {
+            let entry = usage.entry(*chan_id).or_default();
+            entry.0 += 1;
+            entry.1 += amount_msat;
+        }
+        let rows = channels.iter().map(|edge| {
+            let (slots, locked_msat) = usage.get(&edge.channel_id).copied().unwrap_or_default();
+            let peer = if edge.node1_pub == self.target {
+                &edge.node2_pub
+            } else {
+                &edge.node1_pub
+            };
+            let liquidity = locked_msat as f64 / (edge.capacity as f64 * 1000.0).max(1.0);
+            Row::new(vec![
+                edge.channel_id.to_string(),
+                peer.get(..16).unwrap_or(peer).to_string(),
+                edge.capacity.to_string(),
+                format!(
+                    "{} {}/{}",
+                    bar(slots as f64 / MAX_HTLC_SLOTS as f64),
+                    slots,
+                    MAX_HTLC_SLOTS
+                ),
+                format!("{} {:.0}%", bar(liquidity), liquidity * 100.0),
+            ])
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(20),
+                    Constraint::Length(17),
+                    Constraint::Length(10),
+                    Constraint::Length(BAR_WIDTH as u16 + 11),
+                    Constraint::Length(BAR_WIDTH as u16 + 7),
+                ],
+            )
+            .header(Row::new(vec![
+                "channel",
+                "peer",
+                "capacity",
+                "slots",
+                "liquidity",
+            ]))
+            .block(Block::bordered().title(" target channels ")),
+            channels_area,
+        );
+
+        let rows = self.jams.values().map(|htlc| {
+            let expiry = if htlc.expiry_height == 0 {
+                String::from("-")
+            } else {
+                (htlc.expiry_height as i64 - self.height as i64).to_string()
+            };
+            Row::new(vec![
+                hex::encode(&htlc.hash[..4]),
+                format!("{} -> {}", htlc.sender, htlc.receiver),
+                htlc.amount.to_string(),
+                htlc.state.name().to_string(),
+                expiry,
+            ])
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(9),
+                    Constraint::Length(24),
+                    Constraint::Length(10),
+                    Constraint::Length(9),
+                    Constraint::Length(14),
+                ],
+            )
+            .header(Row::new(vec![
+                "hash",
+                "route",
+                "sat",
+                "state",
+                "expires in",
+            ]))
+            .block(Block::bordered().title(format!(" in flight ({}) ", self.jams.len()))),
+            jams_area,
+        );
+
+        frame.render_widget(
+            List::new(self.log.iter().map(String::as_str))
+                .block(Block::bordered().title(" events ")),
+            log_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new(format!(
+                "height {} | cost {} sat | {} | rate {}% | q quit  r release all  p pause  +/- rate",
+                self.height,
+                self.fees_msat / 1000,
+                if CONTROLS.is_paused() {
+                    "paused"
+                } else {
+                    "sending"
+                },
+                CONTROLS.rate_percent(),
+            )),
+            status_area,
+        );
+    }
+}
+
+fn restore(terminal: &mut Terminal<CrosstermBackend<Stderr>>) {
+    disable_raw_mode().unwrap();
+    execute!(terminal.backend_mut(), LeaveAlternateScreen).unwrap();
+    terminal.show_cursor().unwrap();
+}
+
+/// Interactive view of the run drawn on stderr from the event bus, with the target's channels
+/// looked up through `graph`. Output of [`crate::log!`] goes to its event log while it is drawn.
+/// Quitting it releases the payments held by the run, waiting up to [`QUIT_TIMEOUT`] for them,
+/// then exits the process.
+pub fn spawn_dashboard(mut graph: Client, target: String) -> tokio::task::JoinHandle<()> {
+    let channels = Arc::new(Mutex::new(Vec::new()));
+    let refreshed = channels.clone();
+    let graph_target = target.clone();
+    tokio::task::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(30));
+        loop {
+            ticker.tick().await;
+            let edges = graph.graph_get_node_channels(graph_target.clone()).await;
+            *refreshed.lock().unwrap() = edges;
+        }
+    });
+
+    let mut events = events::subscribe();
+    events::capture_output();
+    tokio::task::spawn_blocking(move || {
+        let mut dashboard = Dashboard {
+            target,
+            channels,
+            in_flight: HashMap::new(),
+            jams: BTreeMap::new(),
+            height: 0,
+            fees_msat: 0,
+            log: VecDeque::new(),
+        };
+        enable_raw_mode().unwrap();
+        execute!(stderr(), EnterAlternateScreen).unwrap();
+        let mut terminal = Terminal::new(CrosstermBackend::new(stderr())).unwrap();
+
+        let mut quitting: Option<Instant> = None;
+        loop {
+            loop {
+                match events.try_recv() {
+                    Ok(event) => dashboard.apply(event),
+                    Err(TryRecvError::Lagged(skipped)) => {
+                        dashboard.push_log(format!("skipped {} events", skipped))
+                    }
+                    Err(_) => break,
+                }
+            }
+            terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+            if quitting
+                .is_some_and(|deadline| dashboard.jams.is_empty() || Instant::now() > deadline)
+            {
+                break;
+            }
+
+            if !term::poll(Duration::from_millis(250)).unwrap() {
+                continue;
+            }
+            let term::Event::Key(key) = term::read().unwrap() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') if dashboard.jams.is_empty() => break,
+                KeyCode::Char('q') => {
+                    CONTROLS.release_all();
+                    dashboard.push_log(String::from("releasing all htlcs before quitting"));
+                    quitting = Some(Instant::now() + QUIT_TIMEOUT);
+                }
+                KeyCode::Char('r') => {
+                    CONTROLS.release_all();
+                    dashboard.push_log(String::from("releasing all htlcs"));
+                }
+                KeyCode::Char('p') => {
+                    let paused = CONTROLS.toggle_pause();
+                    dashboard.push_log(String::from(if paused { "paused" } else { "resumed" }));
+                }
+                KeyCode::Char('+') => CONTROLS.set_rate_percent(CONTROLS.rate_percent() * 2),
+                KeyCode::Char('-') => CONTROLS.set_rate_percent(CONTROLS.rate_percent() / 2),
+                _ => {}
+            }
+        }
+        restore(&mut terminal);
+        std::process::exit(0);
+    })
+}
@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use fedimint_tonic_lnd::lnrpc::failure::FailureCode;
+use fedimint_tonic_lnd::routerrpc::htlc_event::Event;
+use fedimint_tonic_lnd::routerrpc::{
+    ForwardHtlcInterceptRequest, ForwardHtlcInterceptResponse, ResolveHoldForwardAction,
+};
+use tokio::time::{Duration, Instant};
+
+use crate::endorsement::EndorsementRules;
+use crate::timeline::unix_ms;
+use crate::Client;
+
+const HEADER: &str = "unix_ms,kind,payment_hash,incoming_chan,incoming_htlc,peer,outgoing_chan,\
+amount_msat,fee_msat,endorsed,reputation_msat,bucket,outcome,resolution_ms";
+
+const ENDORSEMENT_HEADER: &str = "unix_ms,payment_hash,incoming_chan,incoming_htlc,outgoing_chan,\
+amount_msat,expiry_delta,reputation_msat,incoming_endorsed,outgoing_endorsed,rule";
+
+/// Used when the peer did not tell us its `max_accepted_htlcs`.
+const MAX_HTLC_SLOTS: u32 = 483;
+/// How often the cached block height is refreshed. The LND fork has no ChainNotifier to push
+/// new blocks to us.
+const HEIGHT_REFRESH: Duration = Duration::from_secs(10);
+
+/// What to do with an HTLC that fits in neither bucket.
+#[derive(Clone, Copy)]
+pub enum OverflowPolicy {
+    /// Fail it back with TEMPORARY_CHANNEL_FAILURE.
+    Fail,
+    /// Forward it anyway and only log the decision, to measure what the algorithm would do.
+    Resume,
+}
+
+pub struct DefenderConfig {
+    /// Share of each outgoing channel's slots and liquidity reserved for endorsed HTLCs from
+    /// peers with good reputation.
+    pub protected_share: f64,
+    /// HTLCs taking longer than this to resolve count against the incoming peer.
+    pub resolution_period: Duration,
+    /// Net revenue (msat) an incoming peer must have earned us beyond to have good reputation.
+    /// Peers we have no history with never do.
+    pub reputation_threshold_msat: i64,
+    pub overflow: OverflowPolicy,
+    /// Decides the endorsement of the HTLCs we forward.
+    pub endorsement: EndorsementRules,
+}
+
+impl Default for DefenderConfig {
+    fn default() -> Self {
+        DefenderConfig {
+            protected_share: 0.5,
+            resolution_period: Duration::from_secs(90),
+            reputation_threshold_msat: 10_000,
+            overflow: OverflowPolicy::Fail,
+            endorsement: EndorsementRules::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Bucket {
+    General,
+    Protected,
+}
+
+impl Bucket {
+    fn name(bucket: Option<Bucket>) -> &'static str {
+        match bucket {
+            Some(Bucket::General) => "general",
+            Some(Bucket::Protected) => "protected",
+            None => "none",
+        }
+    }
+}
+
+/// Revenue and resolution history of one incoming peer.
+#[derive(Default)]
+struct Reputation {
+    revenue_msat: i64,
+    penalty_msat: i64,
+}
+
+impl Reputation {
+    fn score(&self) -> i64 {
+        self.revenue_msat - self.penalty_msat
+    }
+}
+
+/// Slots and liquidity in use on one outgoing channel, per bucket.
+#[derive(Default)]
+struct Usage {
+    general_slots: u32,
+    general_msat: u64,
+    protected_slots: u32,
+    protected_msat: u64,
+}
+
+struct Limits {
+    slots: u32,
+    msat: u64,
+}
+
+struct InFlight {
+    peer: String,
+    outgoing_chan: u64,
+    amount_msat: u64,
+    fee_msat: i64,
+    bucket: Option<Bucket>,
+    added: Instant,
+}
+
+struct Defender {
+    config: DefenderConfig,
+    peers: HashMap<u64, String>,
+    limits: HashMap<u64, Limits>,
+    reputation: HashMap<String, Reputation>,
+    usage: HashMap<u64, Usage>,
+    in_flight: HashMap<(u64, u64), InFlight>,
+    log: BufWriter<File>,
+    endorsement_log: BufWriter<File>,
+}
+
+impl Defender {
+    async fn refresh_channels(&mut self, client: &mut Client) {
+        for channel in client.list_channels().await {
+            let slots = channel
+                .remote_constraints
+                .map(|c| c.max_accepted_htlcs)
+                .filter(|slots| *slots > 0)
+                .unwrap_or(MAX_HTLC_SLOTS);
+            self.limits.insert(
+                channel.chan_id,
+                Limits {
+                    slots,
+                    msat: channel.capacity as u64 * 1000,
+                },
+            );
+            self.peers.insert(channel.chan_id, channel.remote_pubkey);
+        }
+    }
+
+    /// Returns the (general, protected) limits of an outgoing channel.
+    fn bucket_limits(&self, chan_id: u64) -> (Limits, Limits) {
+        let limits = self.limits.get(&chan_id).unwrap_or(&Limits {
+            slots: MAX_HTLC_SLOTS,
+            msat: u64::MAX,
+        });
+        let protected_slots = (limits.slots as f64 * self.config.protected_share) as u32;
+        let protected_msat = (limits.msat as f64 * self.config.protected_share) as u64;
+        (
+            Limits {
+                slots: limits.slots - protected_slots,
+                msat: limits.msat - protected_msat,
+            },
+            Limits {
+                slots: protected_slots,
+                msat: protected_msat,
+            },
+        )
+    }
+
+    fn pick_bucket(&self, outgoing_chan: u64, amount_msat: u64, protected: bool) -> Option<Bucket> {
+        let (general, protected_limits) = self.bucket_limits(outgoing_chan);
+        let usage = self.usage.get(&outgoing_chan);
+        let (general_slots, general_msat, protected_slots, protected_msat) = usage
+            .map(|u| {
+                (
+                    u.general_slots,
+                    u.general_msat,
+                    u.protected_slots,
+                    u.protected_msat,
+                )
+            })
+            .unwrap_or_default();
+
+        if protected
+            && protected_slots < protected_limits.slots
+            && protected_msat + amount_msat <= protected_limits.msat
+        {
+            Some(Bucket::Protected)
+        } else if general_slots < general.slots && general_msat + amount_msat <= general.msat {
+            Some(Bucket::General)
+        } else {
+            None
+        }
+    }
+
+    fn on_intercept(
+        &mut self,
+        htlc: &ForwardHtlcInterceptRequest,
+        height: u32,
+    ) -> ForwardHtlcInterceptResponse {
+        let key = htlc.incoming_circuit_key.clone().unwrap_or_default();
+        let peer = self.peers.get(&key.chan_id).cloned().unwrap_or_default();
+        let fee_msat = htlc.incoming_amount_msat as i64 - htlc.outgoing_amount_msat as i64;
+        let score = self.reputation.get(&peer).map_or(0, Reputation::score);
+        let good_reputation =
+            self.reputation.contains_key(&peer) && score > self.config.reputation_threshold_msat;
+
+        let bucket = self.pick_bucket(
+            htlc.outgoing_requested_chan_id,
+            htlc.outgoing_amount_msat,
+            htlc.incoming_endorsed && good_reputation,
+        );
+        let resume = bucket.is_some() || matches!(self.config.overflow, OverflowPolicy::Resume);
+
+        writeln!(
+            self.log,
+            "{},decision,{},{},{},{},{},{},{},{},{},{},{},",
+            unix_ms(),
+            hex::encode(&htlc.payment_hash),
+            key.chan_id,
+            key.htlc_id,
+            peer,
+            htlc.outgoing_requested_chan_id,
+            htlc.outgoing_amount_msat,
+            fee_msat,
+            htlc.incoming_endorsed,
+            score,
+            Bucket::name(bucket),
+            if resume { "resume" } else { "fail" },
+        )
+        .unwrap();
+        self.log.flush().unwrap();
+
+        let mut outgoing_endorsed = false;
+        if resume {
+            let expiry_delta = htlc.outgoing_expiry.saturating_sub(height);
+            let (endorsed, rule) = self.config.endorsement.outgoing(
+                htlc.incoming_endorsed,
+                score,
+                htlc.outgoing_amount_msat,
+                expiry_delta,
+            );
+            outgoing_endorsed = endorsed;
+            if htlc.incoming_endorsed != outgoing_endorsed {
+                log!(
+                    "defender: endorsement {} -> {} ({}) for {}",
+                    htlc.incoming_endorsed,
+                    outgoing_endorsed,
+                    rule,
+                    hex::encode(&htlc.payment_hash)
+                );
+            }
+            writeln!(
+                self.endorsement_log,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                unix_ms(),
+                hex::encode(&htlc.payment_hash),
+                key.chan_id,
+                key.htlc_id,
+                htlc.outgoing_requested_chan_id,
+                htlc.outgoing_amount_msat,
+                expiry_delta,
+                score,
+                htlc.incoming_endorsed,
+                outgoing_endorsed,
+                rule,
+            )
+            .unwrap();
+            self.endorsement_log.flush().unwrap();
+
+            let usage = self
+                .usage
+                .entry(htlc.outgoing_requested_chan_id)
+                .or_default();
+            match bucket {
+                Some(Bucket::Protected) => {
+                    usage.protected_slots += 1;
+                    usage.protected_msat += htlc.outgoing_amount_msat;
+                }
+                Some(Bucket::General) => {
+                    usage.general_slots += 1;
+                    usage.general_msat += htlc.outgoing_amount_msat;
+                }
+                None => {}
+            }
+            self.in_flight.insert(
+                (key.chan_id, key.htlc_id),
+                InFlight {
+                    peer,
+                    outgoing_chan: htlc.outgoing_requested_chan_id,
+                    amount_msat: htlc.outgoing_amount_msat,
+                    fee_msat,
+                    bucket,
+                    added: Instant::now(),
+                },
+            );
+        }
+
+        ForwardHtlcInterceptResponse {
+            incoming_circuit_key: Some(key),
+            action: if resume {
+                ResolveHoldForwardAction::Resume as i32
+            } else {
+                ResolveHoldForwardAction::Fail as i32
+            },
+            failure_code: if resume {
+                0
+            } else {
+                FailureCode::TemporaryChannelFailure as i32
+            },
+            outgoing_endorsed,
+            ..Default::default()
+        }
+    }
+
+    fn on_resolve(&mut self, incoming_chan: u64, incoming_htlc: u64, settled: bool) {
+        let Some(htlc) = self.in_flight.remove(&(incoming_chan, incoming_htlc)) else {
+            return;
+        };
+        if let Some(usage) = self.usage.get_mut(&htlc.outgoing_chan) {
+            match htlc.bucket {
+                Some(Bucket::Protected) => {
+                    usage.protected_slots -= 1;
+                    usage.protected_msat -= htlc.amount_msat;
+                }
+                Some(Bucket::General) => {
+                    usage.general_slots -= 1;
+                    usage.general_msat -= htlc.amount_msat;
+                }
+                None => {}
+            }
+        }
+
+        // HTLCs held longer than the resolution period cost the peer the fee it would have paid
+        // for every period it kept the resources locked
+        let resolution = htlc.added.elapsed();
+        let periods = resolution.as_millis() / self.config.resolution_period.as_millis().max(1);
+        let reputation = self.reputation.entry(htlc.peer.clone()).or_default();
+        if settled {
+            reputation.revenue_msat += htlc.fee_msat;
+        }
+        reputation.penalty_msat += htlc.fee_msat.max(1) * periods as i64;
+        let score = reputation.score();
+
+        writeln!(
+            self.log,
+            "{},resolution,,{},{},{},{},{},{},,{},{},{},{}",
+            unix_ms(),
+            incoming_chan,
+            incoming_htlc,
+            htlc.peer,
+            htlc.outgoing_chan,
+            htlc.amount_msat,
+            htlc.fee_msat,
+            score,
+            Bucket::name(htlc.bucket),
+            if settled { "settle" } else { "fail" },
+            resolution.as_millis(),
+        )
+        .unwrap();
+        self.log.flush().unwrap();
+    }
+}
+
+/// Runs a local reputation and resource bucketing defence on the node `client` is connected to,
+/// intercepting every forwarded HTLC. Decisions go to `path`, and the endorsement given to every
+/// forwarded HTLC to `endorsement_path`. Never returns.
+pub async fn run_defender(
+    mut client: Client,
+    config: DefenderConfig,
+    path: &str,
+    endorsement_path: &str,
+) {
+    let mut log = BufWriter::new(File::create(path).unwrap());
+    writeln!(log, "{}", HEADER).unwrap();
+    let mut endorsement_log = BufWriter::new(File::create(endorsement_path).unwrap());
+    writeln!(endorsement_log, "{}", ENDORSEMENT_HEADER).unwrap();
+    let mut defender = Defender {
+        config,
+        peers: HashMap::new(),
+        limits: HashMap::new(),
+        reputation: HashMap::new(),
+        usage: HashMap::new(),
+        in_flight: HashMap::new(),
+        log,
+        endorsement_log,
+    };
+    defender.refresh_channels(&mut client).await;
+
+    let mut events = client.subscribe_htlc_events().await;
+    let (tx, mut htlcs) = client.htlc_interceptor().await;
+    log!("defender: intercepting htlcs");
+
+    let mut height = client.get_block_height().await;
+    let mut refresh_height = tokio::time::interval(HEIGHT_REFRESH);
+    loop {
+        tokio::select! {
+            _ = refresh_height.tick() => {
+                height = client.get_block_height().await;
+            }
+            htlc = htlcs.message() => {
+                let Some(htlc) = htlc.unwrap() else { break };
+                let chan_id = htlc.incoming_circuit_key.as_ref().map_or(0, |key| key.chan_id);
+                if !defender.peers.contains_key(&chan_id) {
+                    defender.refresh_channels(&mut client).await;
+                }
+                let response = defender.on_intercept(&htlc, height);
+                tx.send(response).await.unwrap();
+            }
+            event = events.message() => {
+                let Some(event) = event.unwrap() else { break };
+                let settled = match event.event {
+                    Some(Event::SettleEvent(_)) => true,
+                    Some(Event::ForwardFailEvent(_)) | Some(Event::LinkFailEvent(_)) => false,
+                    _ => continue,
+                };
+                defender.on_resolve(event.incoming_channel_id, event.incoming_htlc_id, settled);
+            }
+        }
+    }
+    log!("defender: stream closed, exiting");
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_tonic_lnd::routerrpc::CircuitKey;
+
+    use super::*;
+
+    const INCOMING: u64 = 1;
+    const OUTGOING: u64 = 2;
+
+    /// A defender whose outgoing channel has 4 slots and 1000 sats, half of them protected,
+    /// and whose incoming channel is with "peer".
+    fn defender(overflow: OverflowPolicy) -> Defender {
+        let log = |name: &str| {
+            let path = std::env::temp_dir().join(format!(
+                "jammy-defender-{}-{}.csv",
+                name,
+                std::process::id()
+            ));
+            BufWriter::new(File::create(path).unwrap())
+        };
+        Defender {
+            config: DefenderConfig {
+                overflow,
+                ..Default::default()
+            },
+            peers: HashMap::from([(INCOMING, String::from("peer"))]),
+            limits: HashMap::from([(
+                OUTGOING,
+                Limits {
+                    slots: 4,
+                    msat: 1_000_000,
+                },
+            )]),
+            reputation: HashMap::new(),
+            usage: HashMap::new(),
+            in_flight: HashMap::new(),
+            log: log("decisions"),
+            endorsement_log: log("endorsements"),
+        }
+    }
+
+    fn set_score(defender: &mut Defender, score: i64) {
+        defender.reputation.insert(
+            String::from("peer"),
+            Reputation {
+                revenue_msat: score,
+                penalty_msat: 0,
+            },
+        );
+    }
+
+    /// Intercepts an endorsed HTLC of `amount_msat` from the peer, returns whether it was resumed
+    /// and the bucket it was put in.
+    fn intercept(
+        defender: &mut Defender,
+        htlc_id: u64,
+        amount_msat: u64,
+    ) -> (bool, Option<Bucket>) {
+        let response = defender.on_intercept(
+            &ForwardHtlcInterceptRequest {
+                incoming_circuit_key: Some(CircuitKey {
+                    chan_id: INCOMING,
+                    htlc_id,
+                }),
+                incoming_amount_msat: amount_msat + 1000,
+                outgoing_amount_msat: amount_msat,
+                outgoing_requested_chan_id: OUTGOING,
+                outgoing_expiry: 140,
+                incoming_endorsed: true,
+                ..Default::default()
+            },
+            100,
+        );
+        let resumed = response.action == ResolveHoldForwardAction::Resume as i32;
+        if !resumed {
+            assert_eq!(
+                response.failure_code,
+                FailureCode::TemporaryChannelFailure as i32
+            );
+        }
+        let bucket = defender
+            .in_flight
+            .get(&(INCOMING, htlc_id))
+            .and_then(|htlc| htlc.bucket);
+        (resumed, bucket)
+    }
+
+    #[test]
+    fn peer_without_history_is_not_protected() {
+        let mut defender = defender(OverflowPolicy::Fail);
+        defender.config.reputation_threshold_msat = -1;
+        assert!(intercept(&mut defender, 0, 1000) == (true, Some(Bucket::General)));
+
+        // the same score with a history is good reputation
+        set_score(&mut defender, 0);
+        assert!(intercept(&mut defender, 1, 1000) == (true, Some(Bucket::Protected)));
+    }
+
+    #[test]
+    fn reputation_must_exceed_the_threshold() {
+        let mut defender = defender(OverflowPolicy::Fail);
+        set_score(&mut defender, 10_000);
+        assert!(intercept(&mut defender, 0, 1000) == (true, Some(Bucket::General)));
+        set_score(&mut defender, 10_001);
+        assert!(intercept(&mut defender, 1, 1000) == (true, Some(Bucket::Protected)));
+    }
+
+    #[test]
+    fn full_buckets_fail_or_resume_by_policy() {
+        for overflow in [OverflowPolicy::Fail, OverflowPolicy::Resume] {
+            let mut defender = defender(overflow);
+            set_score(&mut defender, 20_000);
+            for id in 0..2 {
+                assert!(intercept(&mut defender, id, 1000) == (true, Some(Bucket::Protected)));
+            }
+            // a full protected bucket spills over into the general one
+            for id in 2..4 {
+                assert!(intercept(&mut defender, id, 1000) == (true, Some(Bucket::General)));
+            }
+            let resumed = matches!(overflow, OverflowPolicy::Resume);
+            assert!(intercept(&mut defender, 4, 1000) == (resumed, None));
+            let usage = &defender.usage[&OUTGOING];
+            assert_eq!((usage.protected_slots, usage.general_slots), (2, 2));
+
+            // resolving a protected HTLC frees its slot
+            defender.on_resolve(INCOMING, 0, true);
+            assert!(intercept(&mut defender, 5, 1000) == (true, Some(Bucket::Protected)));
+        }
+    }
+
+    #[test]
+    fn protected_liquidity_is_limited() {
+        let mut defender = defender(OverflowPolicy::Fail);
+        set_score(&mut defender, 20_000);
+        assert!(intercept(&mut defender, 0, 400_000) == (true, Some(Bucket::Protected)));
+        assert!(intercept(&mut defender, 1, 200_000) == (true, Some(Bucket::General)));
+        assert!(intercept(&mut defender, 2, 400_000) == (false, None));
+    }
+}
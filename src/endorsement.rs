@@ -0,0 +1,90 @@
+/// Rules deciding whether an HTLC we forward is endorsed to the next hop.
+pub struct EndorsementRules {
+    /// Endorse outgoing HTLCs even when the incoming one was not endorsed.
+    pub endorse_unendorsed: bool,
+    /// Reputation score (msat) the incoming peer needs for us to keep the endorsement.
+    pub min_reputation_msat: i64,
+    /// Larger outgoing amounts are never endorsed.
+    pub max_amount_msat: u64,
+    /// Outgoing HTLCs expiring further than this many blocks from the current height are never
+    /// endorsed.
+    pub max_expiry_delta: u32,
+}
+
+impl Default for EndorsementRules {
+    fn default() -> Self {
+        EndorsementRules {
+            endorse_unendorsed: false,
+            min_reputation_msat: 0,
+            max_amount_msat: u64::MAX,
+            max_expiry_delta: 2016,
+        }
+    }
+}
+
+impl EndorsementRules {
+    /// Returns the outgoing endorsement, and the rule that decided it.
+    pub fn outgoing(
+        &self,
+        incoming_endorsed: bool,
+        reputation_msat: i64,
+        amount_msat: u64,
+        expiry_delta: u32,
+    ) -> (bool, &'static str) {
+        if !incoming_endorsed && !self.endorse_unendorsed {
+            (false, "incoming_unendorsed")
+        } else if reputation_msat < self.min_reputation_msat {
+            (false, "reputation")
+        } else if amount_msat > self.max_amount_msat {
+            (false, "amount")
+        } else if expiry_delta > self.max_expiry_delta {
+            (false, "cltv")
+        } else {
+            (true, "ok")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_pass_endorsements_on() {
+        let rules = EndorsementRules::default();
+        assert_eq!(rules.outgoing(true, 0, u64::MAX, 2016), (true, "ok"));
+        assert_eq!(rules.outgoing(true, -1, 1000, 40), (false, "reputation"));
+        assert_eq!(rules.outgoing(true, 0, 1000, 2017), (false, "cltv"));
+        assert_eq!(
+            rules.outgoing(false, 1_000_000, 1000, 40),
+            (false, "incoming_unendorsed")
+        );
+    }
+
+    #[test]
+    fn each_rule_withholds_the_endorsement() {
+        let rules = EndorsementRules {
+            endorse_unendorsed: true,
+            min_reputation_msat: 10_000,
+            max_amount_msat: 50_000,
+            max_expiry_delta: 144,
+        };
+        assert_eq!(rules.outgoing(false, 10_000, 50_000, 144), (true, "ok"));
+        assert_eq!(
+            rules.outgoing(true, 9_999, 50_000, 144),
+            (false, "reputation")
+        );
+        assert_eq!(rules.outgoing(true, 10_000, 50_001, 144), (false, "amount"));
+        assert_eq!(rules.outgoing(true, 10_000, 50_000, 145), (false, "cltv"));
+
+        let strict = EndorsementRules {
+            endorse_unendorsed: false,
+            ..rules
+        };
+        assert_eq!(
+            strict.outgoing(false, 10_000, 50_000, 144),
+            (false, "incoming_unendorsed")
+        );
+        assert_eq!(strict.outgoing(true, 10_000, 50_000, 144), (true, "ok"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+
+use fedimint_tonic_lnd::lnrpc::payment::PaymentStatus;
+use fedimint_tonic_lnd::lnrpc::{Payment, PaymentFailureReason};
+use tokio::sync::broadcast;
+
+use crate::invoice_feed::InvoiceEvent;
+use crate::records;
+use crate::strategy::JamHtlc;
+use crate::timeline::{unix_ms, Timeline};
+
+const HEADER: &str = "unix_ms,elapsed_ms,phase,kind,payment_hash,sender,receiver,amount_sat,\
+state,failure_reason,fee_msat,expiry_height,height,endorsed,custom_records";
+
+/// Something that happened during a run. Published on a process-wide bus that the event log and
+/// the dashboard subscribe to.
+#[derive(Clone)]
+pub enum Event {
+    /// Update from the `SendPaymentV2` stream of a payment sent through [`crate::Client`].
+    Payment(Payment),
+    /// A payment sent by a jamming strategy was sent or changed state.
+    Jam(JamHtlc),
+    /// Block height seen by the strategy runtime.
+    Height(u32),
+    /// Change to an invoice of one of our nodes, from [`crate::invoice_feed`].
+    Invoice(InvoiceEvent),
+    /// Line of output, published instead of printed once [`capture_output`] is called.
+    Log(String),
+}
+
+/// Shared by every run in the process, see the [crate documentation](crate).
+static BUS: LazyLock<broadcast::Sender<Event>> = LazyLock::new(|| broadcast::channel(4096).0);
+
+pub fn publish(event: Event) {
+    // nobody listening is fine
+    let _ = BUS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    BUS.subscribe()
+}
+
+/// Whether [`log`] publishes instead of printing, process-wide like the bus.
+static CAPTURED: AtomicBool = AtomicBool::new(false);
+
+/// Publishes the output of [`crate::log!`] on the bus from now on, so that it does not garble
+/// the dashboard.
+pub fn capture_output() {
+    CAPTURED.store(true, Ordering::Relaxed);
+}
+
+/// Prints `line`, or publishes it as [`Event::Log`] once output is captured.
+pub fn log(line: String) {
+    if CAPTURED.load(Ordering::Relaxed) {
+        publish(Event::Log(line));
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Background task appending every event to `path`. Slow consumers skip events rather than
+/// block publishers, skipped events are reported on stdout.
+pub fn spawn_event_log(path: &str, timeline: Timeline) -> tokio::task::JoinHandle<()> {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    writeln!(out, "{}", HEADER).unwrap();
+    let mut events = subscribe();
+
+    tokio::task::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log!("event log: skipped {} events", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let fields = match event {
+                Event::Payment(payment) => format!(
+                    "payment,{},,,{},{},{},{},,,,",
+                    payment.payment_hash,
+                    payment.value_sat,
+                    PaymentStatus::try_from(payment.status)
+                        .map_or("UNKNOWN", |status| status.as_str_name()),
+                    PaymentFailureReason::try_from(payment.failure_reason)
+                        .map_or("UNKNOWN", |reason| reason.as_str_name()),
+                    payment.fee_msat,
+                ),
+                Event::Jam(htlc) => format!(
+                    "jam,{},{},{},{},{},,,{},,,",
+                    hex::encode(htlc.hash),
+                    htlc.sender,
+                    htlc.receiver,
+                    htlc.amount,
+                    htlc.state.name(),
+                    htlc.expiry_height,
+                ),
+                Event::Height(height) => format!("height,,,,,,,,,{},,", height),
+                Event::Invoice(InvoiceEvent::HtlcAccepted {
+                    node,
+                    hash,
+                    amount_msat,
+                    incoming_endorsed,
+                    expiry_height,
+                    custom_records,
+                    ..
+                }) => format!(
+                    "htlc_accepted,{},,{},{},,,,{},,{},{}",
+                    hex::encode(hash),
+                    node,
+                    amount_msat / 1000,
+                    expiry_height,
+                    incoming_endorsed,
+                    records::format(&custom_records),
+                ),
+                Event::Invoice(InvoiceEvent::Accepted {
+                    node,
+                    hash,
+                    amount_msat,
+                }) => format!(
+                    "invoice_accepted,{},,{},{},ACCEPTED,,,,,,",
+                    hex::encode(hash),
+                    node,
+                    amount_msat / 1000,
+                ),
+                Event::Invoice(InvoiceEvent::Settled {
+                    node,
+                    hash,
+                    amount_msat,
+                }) => format!(
+                    "invoice_settled,{},,{},{},SETTLED,,,,,,",
+                    hex::encode(hash),
+                    node,
+                    amount_msat / 1000,
+                ),
+                Event::Invoice(InvoiceEvent::Cancelled { node, hash }) => format!(
+                    "invoice_cancelled,{},,{},,CANCELED,,,,,,",
+                    hex::encode(hash),
+                    node,
+                ),
+                Event::Log(_) => continue,
+            };
+            writeln!(
+                out,
+                "{},{},{},{}",
+                unix_ms(),
+                timeline.elapsed_ms(),
+                timeline.phase(),
+                fields
+            )
+            .unwrap();
+            out.flush().unwrap();
+        }
+    })
+}
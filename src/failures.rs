@@ -0,0 +1,248 @@
+//! Where and why the HTLC attempts of our payments failed, decoded from their onion failures.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use fedimint_tonic_lnd::lnrpc::failure::FailureCode;
+use fedimint_tonic_lnd::lnrpc::htlc_attempt::HtlcStatus;
+use fedimint_tonic_lnd::lnrpc::{ChannelUpdate, HtlcAttempt};
+
+/// The failure of one HTLC attempt, and the hop it came from.
+#[derive(Clone, Debug)]
+pub struct HopFailure {
+    pub code: FailureCode,
+    /// Position of the failing node in the route, 0 being us.
+    pub source_index: u32,
+    /// Pubkey of the failing node, empty when it is us.
+    pub source: String,
+    /// Channel the failing node could not forward over, or the one it received the HTLC over
+    /// when it is the destination.
+    pub chan_id: u64,
+    /// Update of the failing channel sent back with the failure, if any.
+    pub channel_update: Option<ChannelUpdate>,
+    /// Height the failure reports, or the one it was seen at for failures without any.
+    pub height: u32,
+}
+
+/// Why the target failed an HTLC.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Cause {
+    /// TEMPORARY_CHANNEL_FAILURE with a channel update: the outgoing link refused the HTLC, its
+    /// slots or liquidity being used up.
+    Exhausted,
+    /// TEMPORARY_CHANNEL_FAILURE without a channel update: LND sends none when an HTLC
+    /// interceptor fails the HTLC, as reputation based defences such as [`crate::defender`] do.
+    Policy,
+    /// Any other failure code.
+    Other(FailureCode),
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cause::Exhausted => write!(f, "slots or liquidity exhausted"),
+            Cause::Policy => write!(f, "defensive policy"),
+            Cause::Other(code) => write!(f, "{}", code.as_str_name()),
+        }
+    }
+}
+
+impl HopFailure {
+    /// Decodes the failure of `attempt`, seen at `height`. None if it did not fail or came back
+    /// without a failure message.
+    pub fn decode(attempt: &HtlcAttempt, height: u32) -> Option<Self> {
+        if attempt.status != HtlcStatus::Failed as i32 {
+            return None;
+        }
+        let failure = attempt.failure.as_ref()?;
+        let hops = attempt
+            .route
+            .as_ref()
+            .map_or(&[][..], |route| &route.hops[..]);
+        let index = failure.failure_source_index as usize;
+        let source = match index {
+            0 => String::new(),
+            index => hops
+                .get(index - 1)
+                .map_or_else(String::new, |hop| hop.pub_key.clone()),
+        };
+        let chan_id = hops
+            .get(index)
+            .or_else(|| hops.last())
+            .map_or(0, |hop| hop.chan_id);
+        Some(HopFailure {
+            code: FailureCode::try_from(failure.code).unwrap_or(FailureCode::Reserved),
+            source_index: failure.failure_source_index,
+            source,
+            chan_id,
+            channel_update: failure.channel_update.clone(),
+            height: if failure.height > 0 {
+                failure.height
+            } else {
+                height
+            },
+        })
+    }
+
+    pub fn cause(&self) -> Cause {
+        match self.code {
+            FailureCode::TemporaryChannelFailure if self.channel_update.is_some() => {
+                Cause::Exhausted
+            }
+            FailureCode::TemporaryChannelFailure => Cause::Policy,
+            code => Cause::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for HopFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = if self.source.is_empty() {
+            "us"
+        } else {
+            &self.source
+        };
+        write!(
+            f,
+            "{} at hop {} ({}) over {} at height {}",
+            self.code.as_str_name(),
+            self.source_index,
+            source,
+            self.chan_id,
+            self.height
+        )?;
+        if let Some(update) = &self.channel_update {
+            write!(f, ", channel update of {}", update.timestamp)?;
+        }
+        Ok(())
+    }
+}
+
+/// Failures coming from the target, counted by the channel it failed to forward over and cause.
+pub type FailureTable = BTreeMap<u64, BTreeMap<Cause, usize>>;
+
+/// Adds the failures of `attempts` coming from `target` to `table`. `height` stands in for the
+/// height of failures that report none, see [`HopFailure::decode`]; the table itself does not
+/// group by height.
+pub fn tally(table: &mut FailureTable, target: &str, attempts: &[HtlcAttempt], height: u32) {
+    for failure in attempts
+        .iter()
+        .filter_map(|attempt| HopFailure::decode(attempt, height))
+        .filter(|failure| failure.source == target)
+    {
+        *table
+            .entry(failure.chan_id)
+            .or_default()
+            .entry(failure.cause())
+            .or_default() += 1;
+    }
+}
+
+pub fn print_failures(table: &FailureTable) {
+    log!("failures at the target per channel:");
+    for (chan_id, causes) in table {
+        for (cause, count) in causes {
+            log!("  {}: {} {}", chan_id, count, cause);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_tonic_lnd::lnrpc::{Failure, Hop, Route};
+
+    use super::*;
+
+    /// A failed attempt over us -> a -> target -> b, channels 1, 2 and 3.
+    fn attempt(code: FailureCode, source_index: u32, update: bool, height: u32) -> HtlcAttempt {
+        let hop = |chan_id, pub_key: &str| Hop {
+            chan_id,
+            pub_key: String::from(pub_key),
+            ..Default::default()
+        };
+        HtlcAttempt {
+            status: HtlcStatus::Failed as i32,
+            route: Some(Route {
+                hops: vec![hop(1, "a"), hop(2, "target"), hop(3, "b")],
+                ..Default::default()
+            }),
+            failure: Some(Failure {
+                code: code as i32,
+                channel_update: update.then(ChannelUpdate::default),
+                failure_source_index: source_index,
+                height,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn causes_follow_the_code_and_channel_update() {
+        for (code, update, cause) in [
+            (FailureCode::TemporaryChannelFailure, true, Cause::Exhausted),
+            (FailureCode::TemporaryChannelFailure, false, Cause::Policy),
+            (
+                FailureCode::FeeInsufficient,
+                true,
+                Cause::Other(FailureCode::FeeInsufficient),
+            ),
+            (
+                FailureCode::IncorrectOrUnknownPaymentDetails,
+                false,
+                Cause::Other(FailureCode::IncorrectOrUnknownPaymentDetails),
+            ),
+        ] {
+            let failure = HopFailure::decode(&attempt(code, 2, update, 100), 100).unwrap();
+            assert_eq!(failure.cause(), cause, "{:?} update {}", code, update);
+        }
+    }
+
+    #[test]
+    fn source_index_picks_the_failing_hop() {
+        for (index, source, chan_id) in [(0, "", 1), (1, "a", 2), (2, "target", 3), (3, "b", 3)] {
+            let failure = HopFailure::decode(
+                &attempt(FailureCode::TemporaryChannelFailure, index, true, 100),
+                100,
+            )
+            .unwrap();
+            assert_eq!(failure.source, source, "index {}", index);
+            assert_eq!(failure.chan_id, chan_id, "index {}", index);
+        }
+    }
+
+    #[test]
+    fn height_falls_back_to_the_one_seen_at() {
+        let code = FailureCode::TemporaryChannelFailure;
+        assert_eq!(
+            HopFailure::decode(&attempt(code, 2, true, 0), 7)
+                .unwrap()
+                .height,
+            7
+        );
+        assert_eq!(
+            HopFailure::decode(&attempt(code, 2, true, 5), 7)
+                .unwrap()
+                .height,
+            5
+        );
+    }
+
+    #[test]
+    fn only_failures_of_the_target_are_tallied() {
+        let mut succeeded = attempt(FailureCode::TemporaryChannelFailure, 2, true, 100);
+        succeeded.status = HtlcStatus::Succeeded as i32;
+        let attempts = [
+            attempt(FailureCode::TemporaryChannelFailure, 2, true, 100),
+            attempt(FailureCode::TemporaryChannelFailure, 2, true, 100),
+            attempt(FailureCode::TemporaryChannelFailure, 2, false, 100),
+            attempt(FailureCode::TemporaryChannelFailure, 1, true, 100),
+            succeeded,
+        ];
+        let mut table = FailureTable::new();
+        tally(&mut table, "target", &attempts, 100);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[&3][&Cause::Exhausted], 2);
+        assert_eq!(table[&3][&Cause::Policy], 1);
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::backend::{node, LightningBackend};
+
+/// Confirmation target of the fee estimates for funding and transfer transactions.
+pub const CONF_TARGET: i32 = 6;
+/// Reserve LND keeps in the wallet for fee bumping, per anchor channel.
+const ANCHOR_RESERVE_SAT: i64 = 10_000;
+/// Sizes of a P2WPKH input, a P2WSH or P2TR output and the fixed part of a segwit transaction.
+const INPUT_VBYTES: i64 = 68;
+const OUTPUT_VBYTES: i64 = 43;
+const OVERHEAD_VBYTES: i64 = 11;
+/// Smallest P2TR change output LND creates, smaller change is left to the fee.
+const DUST_LIMIT_SAT: i64 = 330;
+/// Inputs assumed when estimating the fee of a funding transaction.
+const FUNDING_INPUTS: i64 = 2;
+/// How long to wait for funding transfers to confirm before giving up on them.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Fee of a transaction spending `inputs` coins to `outputs` outputs plus change.
+fn tx_fee(fee_rate: u64, inputs: i64, outputs: i64) -> i64 {
+    fee_rate as i64 * (OVERHEAD_VBYTES + inputs * INPUT_VBYTES + (outputs + 1) * OUTPUT_VBYTES)
+}
+
+/// On-chain sats needed to open a channel funded with `amount`, counting the funding
+/// transaction's fee and the anchor reserve.
+pub fn channel_cost(amount: i64, fee_rate: u64) -> i64 {
+    amount + tx_fee(fee_rate, FUNDING_INPUTS, 1) + ANCHOR_RESERVE_SAT
+}
+
+/// Whether the largest coins can pay `amount` to `outputs` outputs plus the fee of spending
+/// them. Returns how many coins that takes and the fee, or `None` if they can't. Change below
+/// [`DUST_LIMIT_SAT`] is not created and goes to the fee instead. This is only an estimate:
+/// `SendMany` takes no inputs, so LND selects the coins it actually spends.
+fn select_coins(coins: &[i64], amount: i64, outputs: i64, fee_rate: u64) -> Option<(usize, i64)> {
+    let mut coins = coins.to_vec();
+    coins.sort_unstable_by(|a, b| b.cmp(a));
+    let mut total = 0;
+    for (i, coin) in coins.iter().enumerate() {
+        total += coin;
+        let fee = tx_fee(fee_rate, i as i64 + 1, outputs);
+        if total >= amount + fee + DUST_LIMIT_SAT {
+            return Some((i + 1, fee));
+        }
+        let fee_without_change = fee - fee_rate as i64 * OUTPUT_VBYTES;
+        if total >= amount + fee_without_change {
+            return Some((i + 1, total - amount));
+        }
+    }
+    None
+}
+
+/// Confirmed on-chain balance of one of our nodes against what its channel opens need.
+#[derive(Debug)]
+pub struct WalletStatus {
+    pub node: String,
+    pub balance: i64,
+    pub required: i64,
+}
+
+impl WalletStatus {
+    pub fn shortfall(&self) -> i64 {
+        (self.required - self.balance).max(0)
+    }
+
+    fn surplus(&self) -> i64 {
+        (self.balance - self.required).max(0)
+    }
+}
+
+/// Nodes still short of funds for the channels they are about to open.
+#[derive(Debug)]
+pub struct Underfunded(pub Vec<WalletStatus>);
+
+impl fmt::Display for Underfunded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let short: Vec<_> = self
+            .0
+            .iter()
+            .map(|status| format!("{} short {} sat", status.node, status.shortfall()))
+            .collect();
+        write!(
+            f,
+            "not enough on-chain funds to open channels: {}",
+            short.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for Underfunded {}
+
+/// Looks up the wallet balance of every node in `required`, which maps node names to the sats
+/// they need.
+pub async fn check<B: LightningBackend>(
+    nodes: &mut HashMap<String, B>,
+    required: &BTreeMap<String, i64>,
+) -> Vec<WalletStatus> {
+    let mut statuses = Vec::new();
+    for (name, required) in required {
+        statuses.push(WalletStatus {
+            node: name.clone(),
+            balance: node(nodes, name).wallet_balance().await,
+            required: *required,
+        });
+    }
+    statuses
+}
+
+pub fn print_report(statuses: &[WalletStatus]) {
+    for status in statuses {
+        log!(
+            "{}: {} sat confirmed, {} needed, {} short",
+            status.node,
+            status.balance,
+            status.required,
+            status.shortfall()
+        );
+    }
+}
+
+/// Moves the surplus of our wallets with more than they need to the ones short of funds, with
+/// one transaction per paying node, then waits up to [`CONFIRMATION_TIMEOUT`] for the transfers
+/// to confirm. Nothing is moved if the surplus can't cover every shortfall. Returns the balances
+/// afterwards.
+pub async fn rebalance<B: LightningBackend>(
+    nodes: &mut HashMap<String, B>,
+    required: &BTreeMap<String, i64>,
+    fee_rate: u64,
+) -> Vec<WalletStatus> {
+    let statuses = check(nodes, required).await;
+    let mut short: Vec<(String, i64)> = statuses
+        .iter()
+        .filter(|status| status.shortfall() > 0)
+        .map(|status| (status.node.clone(), status.shortfall()))
+        .collect();
+    if short.is_empty() {
+        return statuses;
+    }
+
+    let mut donors: Vec<&WalletStatus> = statuses
+        .iter()
+        .filter(|status| status.surplus() > 0)
+        .collect();
+    donors.sort_by_key(|status| -status.surplus());
+    let mut transfers: Vec<(String, Vec<(String, i64)>)> = Vec::new();
+    for donor in donors {
+        let coins = node(nodes, &donor.node).list_unspent().await;
+        // keep enough to pay for spending every coin to every short node
+        let mut available =
+            donor.surplus() - tx_fee(fee_rate, coins.len() as i64, short.len() as i64);
+        let mut outputs = Vec::new();
+        for (recipient, missing) in &short {
+            let amount = (*missing).min(available);
+            if amount <= 0 {
+                break;
+            }
+            outputs.push((recipient.clone(), amount));
+            available -= amount;
+        }
+        if outputs.is_empty() {
+            continue;
+        }
+        let total = outputs.iter().map(|(_, amount)| amount).sum();
+        let Some((inputs, fee)) = select_coins(&coins, total, outputs.len() as i64, fee_rate)
+        else {
+            continue;
+        };
+        log!(
+            "{} pays {} sat, about {} of its {} coins and {} sat in fees",
+            donor.node,
+            total,
+            inputs,
+            coins.len(),
+            fee
+        );
+        for ((_, missing), (_, amount)) in short.iter_mut().zip(&outputs) {
+            *missing -= amount;
+        }
+        short.retain(|(_, missing)| *missing > 0);
+        transfers.push((donor.node.clone(), outputs));
+    }
+    if !short.is_empty() {
+        log!("our nodes can't cover each other's shortfall, not moving funds");
+        return statuses;
+    }
+
+    for (donor, outputs) in transfers {
+        let mut addresses = HashMap::new();
+        for (recipient, amount) in outputs {
+            let address = node(nodes, &recipient).new_address().await;
+            addresses.insert(address, amount);
+        }
+        node(nodes, &donor).send_many(addresses, fee_rate).await;
+    }
+
+    log!("waiting for funding transfers to confirm...");
+    let deadline = Instant::now() + CONFIRMATION_TIMEOUT;
+    loop {
+        let statuses = check(nodes, required).await;
+        if statuses.iter().all(|status| status.shortfall() == 0) {
+            return statuses;
+        }
+        if Instant::now() >= deadline {
+            log!(
+                "funding transfers not confirmed after {} s",
+                CONFIRMATION_TIMEOUT.as_secs()
+            );
+            return statuses;
+        }
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Makes sure every node can afford the channels it is about to open, `channels` being
+/// (node, funding amount) pairs. With `rebalance`, funds are first moved between our nodes as
+/// needed. Fails with the nodes still short otherwise.
+pub async fn ensure_funded<B: LightningBackend>(
+    nodes: &mut HashMap<String, B>,
+    channels: &[(String, i64)],
+    rebalance: bool,
+) -> Result<(), Underfunded> {
+    let Some(any) = nodes.values_mut().next() else {
+        return Ok(());
+    };
+    let fee_rate = any.estimate_fee_rate(CONF_TARGET).await;
+    let mut required = BTreeMap::new();
+    for (name, amount) in channels {
+        *required.entry(name.clone()).or_default() += channel_cost(*amount, fee_rate);
+    }
+
+    let statuses = if rebalance {
+        self::rebalance(nodes, &required, fee_rate).await
+    } else {
+        check(nodes, &required).await
+    };
+    print_report(&statuses);
+    let short: Vec<_> = statuses
+        .into_iter()
+        .filter(|status| status.shortfall() > 0)
+        .collect();
+    if short.is_empty() {
+        Ok(())
+    } else {
+        Err(Underfunded(short))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_counts_inputs_outputs_and_change() {
+        assert_eq!(tx_fee(1, 1, 1), 11 + 68 + 2 * 43);
+        assert_eq!(tx_fee(10, 2, 3), 10 * (11 + 2 * 68 + 4 * 43));
+    }
+
+    #[test]
+    fn exact_fit_pays_no_change() {
+        let fee = tx_fee(2, 1, 1) - 2 * OUTPUT_VBYTES;
+        assert_eq!(
+            select_coins(&[100_000 + fee], 100_000, 1, 2),
+            Some((1, fee))
+        );
+    }
+
+    #[test]
+    fn largest_coins_are_spent_first() {
+        let coins = [1_000, 60_000, 5_000, 50_000];
+        assert_eq!(
+            select_coins(&coins, 100_000, 1, 1),
+            Some((2, tx_fee(1, 2, 1)))
+        );
+    }
+
+    #[test]
+    fn insufficient_funds_select_nothing() {
+        let fee = tx_fee(2, 2, 1) - 2 * OUTPUT_VBYTES;
+        assert_eq!(
+            select_coins(&[60_000, 40_000 + fee - 1], 100_000, 1, 2),
+            None
+        );
+        assert_eq!(select_coins(&[], 1, 1, 2), None);
+    }
+
+    #[test]
+    fn dust_change_goes_to_the_fee() {
+        let fee = tx_fee(2, 1, 1);
+        let coin = 100_000 + fee + DUST_LIMIT_SAT - 1;
+        assert_eq!(
+            select_coins(&[coin], 100_000, 1, 2),
+            Some((1, coin - 100_000))
+        );
+        assert_eq!(select_coins(&[coin + 1], 100_000, 1, 2), Some((1, fee)));
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::timeline::{unix_ms, Timeline};
+use crate::Client;
+
+const HEADER: &str = "unix_ms,elapsed_ms,phase,kind,chan_id,advertising_node,connecting_node,\
+capacity,disabled,fee_base_msat,fee_rate_milli_msat,time_lock_delta,max_htlc_msat,closed_height";
+
+/// Background task that follows `SubscribeChannelGraph` and records every channel update and
+/// channel close touching `target` to `path`, so defensive reactions (fee bumps, disabled
+/// channels, closes) can be lined up with the rest of the experiment.
+pub fn spawn_graph_watcher(
+    mut client: Client,
+    target: String,
+    path: &str,
+    timeline: Timeline,
+) -> tokio::task::JoinHandle<()> {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    writeln!(out, "{}", HEADER).unwrap();
+
+    tokio::task::spawn(async move {
+        // closed channel updates only carry the channel id, so keep track of which ones are
+        // the target's
+        let mut target_chans: HashSet<u64> = client
+            .graph_get_node_channels(target.clone())
+            .await
+            .iter()
+            .map(|edge| edge.channel_id)
+            .collect();
+
+        let mut stream = client.subscribe_channel_graph().await;
+        while let Some(update) = stream.message().await.unwrap() {
+            let now = unix_ms();
+            let elapsed = timeline.elapsed_ms();
+            let phase = timeline.phase();
+
+            for edge in update.channel_updates {
+                if edge.advertising_node != target && edge.connecting_node != target {
+                    continue;
+                }
+                target_chans.insert(edge.chan_id);
+                let policy = edge.routing_policy.unwrap_or_default();
+                log!(
+                    "graph: {} updated channel {} (disabled: {}, fee: {}/{})",
+                    edge.advertising_node,
+                    edge.chan_id,
+                    policy.disabled,
+                    policy.fee_base_msat,
+                    policy.fee_rate_milli_msat
+                );
+                writeln!(
+                    out,
+                    "{},{},{},edge_update,{},{},{},{},{},{},{},{},{},",
+                    now,
+                    elapsed,
+                    phase,
+                    edge.chan_id,
+                    edge.advertising_node,
+                    edge.connecting_node,
+                    edge.capacity,
+                    policy.disabled,
+                    policy.fee_base_msat,
+                    policy.fee_rate_milli_msat,
+                    policy.time_lock_delta,
+                    policy.max_htlc_msat,
+                )
+                .unwrap();
+            }
+
+            for closed in update.closed_chans {
+                if !target_chans.remove(&closed.chan_id) {
+                    continue;
+                }
+                log!(
+                    "graph: channel {} closed at height {}",
+                    closed.chan_id,
+                    closed.closed_height
+                );
+                writeln!(
+                    out,
+                    "{},{},{},closed,{},,,{},,,,,,{}",
+                    now, elapsed, phase, closed.chan_id, closed.capacity, closed.closed_height,
+                )
+                .unwrap();
+            }
+            out.flush().unwrap();
+        }
+    })
+}
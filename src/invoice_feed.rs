@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{Invoice, InvoiceHtlcState};
+use tokio::time::{sleep, Duration};
+
+use crate::events::{self, Event};
+use crate::metrics;
+use crate::records::CustomRecords;
+use crate::Client;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Change to an invoice of one of our nodes, as seen by [`spawn_invoice_feed`].
+#[derive(Clone)]
+pub enum InvoiceEvent {
+    /// An HTLC paying the invoice arrived and is held.
+    HtlcAccepted {
+        node: String,
+        hash: Vec<u8>,
+        chan_id: u64,
+        htlc_index: u64,
+        amount_msat: u64,
+        incoming_endorsed: bool,
+        expiry_height: u32,
+        /// Custom records of the htlc, see [`crate::records`].
+        custom_records: CustomRecords,
+    },
+    /// Every HTLC of a hold invoice has arrived, it can now be settled or cancelled.
+    Accepted {
+        node: String,
+        hash: Vec<u8>,
+        amount_msat: i64,
+    },
+    Settled {
+        node: String,
+        hash: Vec<u8>,
+        amount_msat: i64,
+    },
+    Cancelled {
+        node: String,
+        hash: Vec<u8>,
+    },
+}
+
+impl InvoiceEvent {
+    pub fn hash(&self) -> &[u8] {
+        match self {
+            InvoiceEvent::HtlcAccepted { hash, .. }
+            | InvoiceEvent::Accepted { hash, .. }
+            | InvoiceEvent::Settled { hash, .. }
+            | InvoiceEvent::Cancelled { hash, .. } => hash,
+        }
+    }
+}
+
+/// What was already reported about an open invoice.
+#[derive(Default)]
+struct Known {
+    state: i32,
+    htlcs: HashSet<u64>,
+}
+
+/// Turns an invoice update into the events that happened since the last one.
+fn changes(
+    node: &str,
+    known: &mut HashMap<Vec<u8>, Known>,
+    invoice: &Invoice,
+) -> Vec<InvoiceEvent> {
+    let mut events = Vec::new();
+    let entry = known.entry(invoice.r_hash.clone()).or_default();
+    for htlc in &invoice.htlcs {
+        if htlc.state != InvoiceHtlcState::Canceled as i32 && entry.htlcs.insert(htlc.htlc_index) {
+            events.push(InvoiceEvent::HtlcAccepted {
+                node: String::from(node),
+                hash: invoice.r_hash.clone(),
+                chan_id: htlc.chan_id,
+                htlc_index: htlc.htlc_index,
+                amount_msat: htlc.amt_msat,
+                incoming_endorsed: htlc.incoming_endorsed,
+                expiry_height: htlc.expiry_height as u32,
+                custom_records: htlc.custom_records.clone(),
+            });
+        }
+    }
+    if invoice.state == entry.state {
+        return events;
+    }
+    entry.state = invoice.state;
+    let node = String::from(node);
+    let hash = invoice.r_hash.clone();
+    match InvoiceState::try_from(invoice.state) {
+        Ok(InvoiceState::Accepted) => events.push(InvoiceEvent::Accepted {
+            node,
+            hash,
+            amount_msat: invoice.amt_paid_msat,
+        }),
+        Ok(InvoiceState::Settled) => {
+            known.remove(&invoice.r_hash);
+            events.push(InvoiceEvent::Settled {
+                node,
+                hash,
+                amount_msat: invoice.amt_paid_msat,
+            });
+        }
+        Ok(InvoiceState::Canceled) => {
+            known.remove(&invoice.r_hash);
+            events.push(InvoiceEvent::Cancelled { node, hash });
+        }
+        _ => {}
+    }
+    events
+}
+
+/// Background task following `SubscribeInvoices` on `client` and publishing an
+/// [`Event::Invoice`] for every HTLC accepted and every invoice accepted, settled or cancelled.
+///
+/// The subscription is resumed from the last add and settle index after errors. LND only
+/// replays added and settled invoices, so HTLCs accepted or cancelled while disconnected are
+/// reported on their next update.
+pub fn spawn_invoice_feed(node: String, mut client: Client) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let (mut add_index, mut settle_index) = (0, 0);
+        let mut known = HashMap::new();
+        loop {
+            let mut invoices = match client.subscribe_invoices(add_index, settle_index).await {
+                Ok(invoices) => invoices,
+                Err(status) => {
+                    log!(
+                        "{}: invoice subscription failed: {}",
+                        node,
+                        status.message()
+                    );
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            loop {
+                match invoices.message().await {
+                    Ok(Some(invoice)) => {
+                        add_index = add_index.max(invoice.add_index);
+                        settle_index = settle_index.max(invoice.settle_index);
+                        metrics::record_invoice(&invoice);
+                        for event in changes(&node, &mut known, &invoice) {
+                            events::publish(Event::Invoice(event));
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(status) => {
+                        log!("{}: invoice subscription lost: {}", node, status.message());
+                        break;
+                    }
+                }
+            }
+            sleep(RECONNECT_DELAY).await;
+        }
+    })
+}
@@ -0,0 +1,64 @@
+//! Channel jamming experiments against LND nodes.
+//!
+//! [`Client`] wraps an LND connection, [`strategy`] drives jamming attacks and [`scenario`] runs
+//! them against any [`backend::LightningBackend`] (a real node or the [`sim`] network), [`traffic`] sends honest
+//! payments and collects their outcomes, and [`defender`] runs a reputation based defence on a
+//! routing node. The `jammy` binary wires these together from build-time configuration.
+//!
+//! The event bus ([`events`]), the metrics registry ([`metrics`]) and the strategy controls
+//! ([`strategy::CONTROLS`]) are process-wide. Runs in the same process, such as tests running in
+//! parallel, share them: they see each other's events, add up in the same metrics and are paused
+//! or released together.
+
+/// Like `println!`, but shown in the dashboard instead while it is drawn, see [`events::log`].
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::events::log(format!($($arg)*))
+    };
+}
+
+pub mod backend;
+pub mod channel_log;
+pub mod channel_point;
+mod client;
+pub mod dashboard;
+pub mod defender;
+pub mod endorsement;
+pub mod events;
+pub mod failures;
+pub mod funding;
+pub mod graph_watch;
+pub mod invoice_feed;
+pub mod metrics;
+pub mod mission_control;
+pub mod mpp;
+pub mod probe;
+pub mod records;
+pub mod sampler;
+pub mod scenario;
+pub mod setup;
+pub mod sim;
+pub mod spontaneous;
+pub mod strategy;
+pub mod teardown;
+pub mod timeline;
+pub mod traffic;
+
+pub use client::Client;
+
+/// Generates `n` random (preimage, payment hash) pairs.
+pub fn gen_hash_table(n: usize) -> Vec<([u8; 32], [u8; 32])> {
+    use bitcoin_hashes::sha256;
+    use bitcoin_hashes::Hash;
+    use rand::{thread_rng, Rng};
+    let mut rng = thread_rng();
+    let mut hash_table = Vec::with_capacity(n);
+    for _ in 0..n {
+        let mut preimage = [0u8; 32];
+        rng.fill(&mut preimage[..]);
+        let hash = sha256::Hash::hash(&preimage);
+        hash_table.push((preimage, hash.to_byte_array()));
+    }
+    hash_table
+}
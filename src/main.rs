@@ -1,3 +1,9 @@
+use jammy::backend::LightningBackend;
+use jammy::{
+    channel_log, dashboard, defender, endorsement, events, gen_hash_table, graph_watch,
+    invoice_feed, log, metrics, records, sampler, scenario, setup, sim, strategy, teardown,
+    timeline, traffic, Client,
+};
 use tokio::time::{sleep, Duration};
 
 const LND_0_RPCSERVER: &str = env!("LND_0_RPCSERVER");
@@ -8,271 +14,311 @@ const LND_1_RPCSERVER: &str = env!("LND_1_RPCSERVER");
 const LND_1_CERT: &str = env!("LND_1_CERT");
 const LND_1_MACAROON: &str = env!("LND_1_MACAROON");
 
-const _LND_2_RPCSERVER: &str = env!("LND_2_RPCSERVER");
-const _LND_2_CERT: &str = env!("LND_2_CERT");
-const _LND_2_MACAROON: &str = env!("LND_2_MACAROON");
+// node running the defender when started as `jammy defend`
+const LND_2_RPCSERVER: &str = env!("LND_2_RPCSERVER");
+const LND_2_CERT: &str = env!("LND_2_CERT");
+const LND_2_MACAROON: &str = env!("LND_2_MACAROON");
 
 const TARGET: &str = env!("TARGET");
 
+const CHANNELS_FILE: Option<&str> = option_env!("CHANNELS_FILE");
+const DEFENDER_LOG_FILE: Option<&str> = option_env!("DEFENDER_LOG_FILE");
+const DEFENDER_OVERFLOW: Option<&str> = option_env!("DEFENDER_OVERFLOW");
+const ENDORSEMENT_LOG_FILE: Option<&str> = option_env!("ENDORSEMENT_LOG_FILE");
+// rules `jammy defend` endorses the HTLCs it forwards by, unset ones keep their defaults
+const ENDORSE_UNENDORSED: Option<&str> = option_env!("ENDORSE_UNENDORSED");
+const ENDORSEMENT_MIN_REPUTATION_MSAT: Option<&str> =
+    option_env!("ENDORSEMENT_MIN_REPUTATION_MSAT");
+const ENDORSEMENT_MAX_AMOUNT_MSAT: Option<&str> = option_env!("ENDORSEMENT_MAX_AMOUNT_MSAT");
+const ENDORSEMENT_MAX_EXPIRY_DELTA: Option<&str> = option_env!("ENDORSEMENT_MAX_EXPIRY_DELTA");
+const EVENTS_FILE: Option<&str> = option_env!("EVENTS_FILE");
+const GRAPH_UPDATES_FILE: Option<&str> = option_env!("GRAPH_UPDATES_FILE");
+const METRICS_ADDR: Option<&str> = option_env!("METRICS_ADDR");
+// mission control snapshots of `jammy run`, kept until restored
+const MISSION_CONTROL_FILE: Option<&str> = option_env!("MISSION_CONTROL_FILE");
+// honest background traffic is only sent when both nodes are configured, and is routed
+// through the target so the receiver should be one of its peers
+const HONEST_SENDER_RPCSERVER: Option<&str> = option_env!("HONEST_SENDER_RPCSERVER");
+const HONEST_SENDER_CERT: Option<&str> = option_env!("HONEST_SENDER_CERT");
+const HONEST_SENDER_MACAROON: Option<&str> = option_env!("HONEST_SENDER_MACAROON");
+const HONEST_RECEIVER_RPCSERVER: Option<&str> = option_env!("HONEST_RECEIVER_RPCSERVER");
+const HONEST_RECEIVER_CERT: Option<&str> = option_env!("HONEST_RECEIVER_CERT");
+const HONEST_RECEIVER_MACAROON: Option<&str> = option_env!("HONEST_RECEIVER_MACAROON");
+const HONEST_RATE: Option<&str> = option_env!("HONEST_RATE");
+const HONEST_AMOUNT: Option<&str> = option_env!("HONEST_AMOUNT");
+const TRAFFIC_FILE: Option<&str> = option_env!("TRAFFIC_FILE");
+// set to move on-chain funds between alice and bob when one can't afford its channel.
+const REBALANCE_WALLETS: Option<&str> = option_env!("REBALANCE_WALLETS");
+// `jammy teardown` sends what is left in the wallets there after closing our channels
+const SWEEP_ADDRESS: Option<&str> = option_env!("SWEEP_ADDRESS");
+const SAMPLES_FILE: Option<&str> = option_env!("SAMPLES_FILE");
+const SAMPLE_INTERVAL_SECS: Option<&str> = option_env!("SAMPLE_INTERVAL_SECS");
+// custom records sent with the jamming payments, as `type=hex value,...`
+const CUSTOM_RECORDS: Option<&str> = option_env!("CUSTOM_RECORDS");
+
 #[tokio::main]
 async fn main() {
-    let mut alice = Client(
-        fedimint_tonic_lnd::connect(
-            format!("https://{}:10009", LND_0_RPCSERVER),
-            LND_0_CERT,
-            LND_0_MACAROON,
-        )
-        .await
-        .unwrap(),
+    let args: Vec<String> = std::env::args().collect();
+    let scenario = match args.get(1).map(String::as_str) {
+        Some("defend") => return defend().await,
+        Some("teardown") => return teardown().await,
+        Some("simulate") => {
+            return simulate(args.get(2).expect("usage: jammy simulate <scenario.toml>")).await
+        }
+        Some("run") => {
+            let mut scenario =
+                load_scenario(args.get(2).expect("usage: jammy run <scenario.toml>"));
+            scenario.mission_control_file = Some(String::from(
+                MISSION_CONTROL_FILE.unwrap_or("mission_control.toml"),
+            ));
+            Some(scenario)
+        }
+        _ => None,
+    };
+
+    let mut alice = Client::connect(LND_0_RPCSERVER, LND_0_CERT, LND_0_MACAROON).await;
+    let mut bob = Client::connect(LND_1_RPCSERVER, LND_1_CERT, LND_1_MACAROON).await;
+
+    let timeline = timeline::Timeline::start();
+    metrics::set_target(TARGET);
+    let _metrics = metrics::spawn_metrics_server(METRICS_ADDR.unwrap_or("127.0.0.1:9184"));
+    let _event_log = events::spawn_event_log(EVENTS_FILE.unwrap_or("events.csv"), timeline.clone());
+    let _invoice_feeds = [
+        invoice_feed::spawn_invoice_feed(String::from("alice"), alice.clone()),
+        invoice_feed::spawn_invoice_feed(String::from("bob"), bob.clone()),
+    ];
+    // the dashboard shows our output in its event log while it is drawn
+    if args.iter().any(|arg| arg == "--tui") {
+        dashboard::spawn_dashboard(alice.clone(), String::from(TARGET));
+    }
+    let _sampler = sampler::spawn_sampler(
+        alice.clone(),
+        vec![("alice", alice.clone()), ("bob", bob.clone())],
+        String::from(TARGET),
+        SAMPLES_FILE.unwrap_or("samples.csv"),
+        Duration::from_secs(SAMPLE_INTERVAL_SECS.map_or(10, |s| s.parse().unwrap())),
+        timeline.clone(),
     );
-    let mut bob = Client(
-        fedimint_tonic_lnd::connect(
-            format!("https://{}:10009", LND_1_RPCSERVER),
-            LND_1_CERT,
-            LND_1_MACAROON,
-        )
-        .await
-        .unwrap(),
+    let _graph_watcher = graph_watch::spawn_graph_watcher(
+        alice.clone(),
+        String::from(TARGET),
+        GRAPH_UPDATES_FILE.unwrap_or("graph_updates.csv"),
+        timeline.clone(),
     );
 
-
-    let target_peers = alice.graph_get_node_peers(String::from(TARGET)).await;
-    alice
-        .open_channel(target_peers[1].clone(), 500_000, 0)
+    let traffic_stats = traffic::TrafficStats::default();
+    if let (Some(sender), Some(receiver)) = (HONEST_SENDER_RPCSERVER, HONEST_RECEIVER_RPCSERVER) {
+        let sender = Client::connect(
+            sender,
+            HONEST_SENDER_CERT.unwrap(),
+            HONEST_SENDER_MACAROON.unwrap(),
+        )
         .await;
-    bob.open_channel(target_peers[2].clone(), 500_000, 250_000)
+        let receiver = Client::connect(
+            receiver,
+            HONEST_RECEIVER_CERT.unwrap(),
+            HONEST_RECEIVER_MACAROON.unwrap(),
+        )
         .await;
-    println!("Please confirm the channels!");
+        let overrides = scenario.as_ref().and_then(|s| s.traffic.as_ref());
+        let _traffic = traffic::spawn_traffic(
+            vec![("honest_sender", sender, "honest_receiver", receiver)],
+            honest_traffic(overrides),
+            TRAFFIC_FILE.unwrap_or("traffic.csv"),
+            timeline.clone(),
+            traffic_stats.clone(),
+        );
+    }
+
+    if let Some(scenario) = scenario {
+        let events = strategy::subscribe_events(alice.clone()).await;
+        let nodes = [(String::from("alice"), alice), (String::from("bob"), bob)];
+        let results = scenario::run(
+            scenario,
+            nodes.into_iter().collect(),
+            String::from(TARGET),
+            Some(events),
+            Some(channel_log::ChannelLog::open(
+                CHANNELS_FILE.unwrap_or("channels.csv"),
+            )),
+            traffic_stats,
+            timeline,
+        )
+        .await;
+        std::process::exit(if results.passed { 0 } else { 1 });
+    }
+
+    let target_peers = alice.graph_get_node_peers(String::from(TARGET)).await;
+    let mut nodes = [
+        (String::from("alice"), alice.clone()),
+        (String::from("bob"), bob.clone()),
+    ]
+    .into_iter()
+    .collect();
+    setup::open_channels(
+        &mut nodes,
+        &[
+            setup::ChannelRequest {
+                node: String::from("alice"),
+                peer: target_peers[1].clone(),
+                amount: 500_000,
+                push: 0,
+                options: setup::ChannelOptions::default(),
+            },
+            setup::ChannelRequest {
+                node: String::from("bob"),
+                peer: target_peers[2].clone(),
+                amount: 500_000,
+                push: 250_000,
+                options: setup::ChannelOptions::default(),
+            },
+        ],
+        REBALANCE_WALLETS.is_some(),
+        Some(&mut channel_log::ChannelLog::open(
+            CHANNELS_FILE.unwrap_or("channels.csv"),
+        )),
+    )
+    .await
+    .unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    });
+    log!("Please confirm the channels!");
     std::io::stdin().read_line(&mut String::new()).unwrap();
 
+    timeline.set_phase("jam");
+
+    let custom_records = records::parse(CUSTOM_RECORDS.unwrap_or("")).unwrap();
     let hash_table = gen_hash_table(10);
 
     for (i, (preimage, hash)) in hash_table.iter().enumerate() {
-        println!("generating invoice...");
+        log!("generating invoice...");
         let invoice = bob.add_hold_invoice(hash.to_vec(), 1000).await;
-        println!("sending payment...");
-        alice.send_payment(invoice.to_string()).await;
-        println!("payment sent! settling invoice...");
+        log!("sending payment...");
+        alice
+            .send_payment_with(invoice.to_string(), None, custom_records.clone())
+            .await;
+        log!("payment sent! settling invoice...");
         sleep(Duration::from_secs(3)).await;
         bob.settle_invoice(preimage.to_vec()).await;
-        // prints whether the inbound htlcs to pay that invoice were endorsed
+        // prints whether the inbound htlcs to pay that invoice were endorsed, and their records
         bob.lookup_invoice(hash.to_vec()).await;
-        println!("settled invoice: {}, {}", i, hex::encode(hash));
+        log!("settled invoice: {}, {}", i, hex::encode(hash));
     }
+    timeline.set_phase("done");
 }
 
-struct Client(fedimint_tonic_lnd::Client);
-
-#[allow(dead_code)]
-impl Client {
-    async fn get_pubkey(&mut self) -> String {
-        self.0
-            .lightning()
-            .get_info(fedimint_tonic_lnd::lnrpc::GetInfoRequest {})
-            .await
-            .unwrap()
-            .into_inner()
-            .identity_pubkey
-    }
-
-    async fn graph_get_node_peers(&mut self, node_pubkey: String) -> Vec<String> {
-        let channels = self
-            .0
-            .lightning()
-            .get_node_info(fedimint_tonic_lnd::lnrpc::NodeInfoRequest {
-                pub_key: node_pubkey.clone(),
-                include_channels: true,
-            })
-            .await
-            .unwrap()
-            .into_inner()
-            .channels;
-        channels
-            .iter()
-            .map(|channel| {
-                if channel.node1_pub == node_pubkey {
-                    channel.node2_pub.clone()
-                } else {
-                    channel.node1_pub.clone()
-                }
-            })
-            .collect()
-    }
-
-    async fn open_channel(
-        &mut self,
-        node_pubkey: String,
-        local_funding_amount: i64,
-        push_sat: i64,
-    ) {
-        use fedimint_tonic_lnd::lnrpc::channel_point::FundingTxid;
-        let res = self
-            .0
-            .lightning()
-            .open_channel_sync(fedimint_tonic_lnd::lnrpc::OpenChannelRequest {
-                node_pubkey: hex::decode(&node_pubkey).unwrap(),
-                local_funding_amount,
-                push_sat,
-                ..Default::default()
-            })
-            .await
-            .unwrap()
-            .into_inner();
-        let s = match res.funding_txid.unwrap() {
-            FundingTxid::FundingTxidBytes(b) => hex::encode(b),
-            FundingTxid::FundingTxidStr(_s) => unreachable!(),
-        };
-        println!("{}", s);
-    }
-
-    async fn add_hold_invoice(&mut self, hash: Vec<u8>, value: i64) -> String {
-        self.0
-            .invoices()
-            .add_hold_invoice(fedimint_tonic_lnd::invoicesrpc::AddHoldInvoiceRequest {
-                hash,
-                value,
-                ..Default::default()
-            })
-            .await
-            .unwrap()
-            .into_inner()
-            .payment_request
-    }
-
-    async fn send_payment(&mut self, payment_request: String) {
-        let mut stream = self
-            .0
-            .router()
-            .send_payment_v2(fedimint_tonic_lnd::routerrpc::SendPaymentRequest {
-                payment_request,
-                fee_limit_sat: 100_000,
-                timeout_seconds: 100_000,
-                endorsed: 1i32,
-                ..Default::default()
-            })
-            .await
-            .unwrap()
-            .into_inner();
-        tokio::task::spawn(async move {
-            while let Some(payment) = stream.message().await.unwrap() {
-                if payment.status == 3 {
-                    println!("payment failed!");
-                } else if payment.status == 2 {
-                    println!("payment success!");
-                }
-            }
-        });
-    }
-
-    async fn settle_invoice(&mut self, preimage: Vec<u8>) {
-        let _res = self
-            .0
-            .invoices()
-            .settle_invoice(fedimint_tonic_lnd::invoicesrpc::SettleInvoiceMsg { preimage })
-            .await
-            .unwrap()
-            .into_inner();
-        //println!("{:?}", res);
-    }
-
-    async fn subscribe_invoices(&mut self) {
-        let mut invoice_stream = self
-            .0
-            .lightning()
-            .subscribe_invoices(fedimint_tonic_lnd::lnrpc::InvoiceSubscription {
-                add_index: 0,
-                settle_index: 0,
-            })
-            .await
-            .expect("Failed to call subscribe_invoices")
-            .into_inner();
-
-        tokio::task::spawn(async move {
-            while let Some(invoice) = invoice_stream
-                .message()
-                .await
-                .expect("Failed to receive invoices")
-            {
-                let htlcs = invoice.htlcs;
-                for htlc in htlcs {
-                    if htlc.incoming_endorsed {
-                        println!("HTLC endorsed!!");
-                    } else {
-                        println!("not endorsed");
-                    }
-                }
-            }
+/// Honest traffic settings of the scenario, or of the build environment where it has none,
+/// exiting if they are invalid.
+fn honest_traffic(overrides: Option<&scenario::TrafficSection>) -> traffic::TrafficConfig {
+    let rate = match overrides.and_then(|o| o.rate) {
+        Some(rate) => Ok(rate),
+        None => HONEST_RATE.map_or(Ok(0.2), |s| {
+            s.parse()
+                .map_err(|_| format!("invalid traffic rate: {}", s))
+                .and_then(traffic::check_rate)
+        }),
+    };
+    let amount = match overrides.and_then(|o| o.amount) {
+        Some(amount) => Ok(amount),
+        None => traffic::AmountDistribution::parse(HONEST_AMOUNT.unwrap_or("lognormal:9.0:1.0")),
+    };
+    let (rate_per_sec, amount) = rate
+        .and_then(|rate| Ok((rate, amount?)))
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
         });
+    traffic::TrafficConfig {
+        rate_per_sec,
+        amount,
+        last_hop_pubkey: Some(String::from(TARGET)),
+        endorsed: true,
+        timeout_seconds: 60,
     }
+}
 
-    async fn lookup_invoice(&mut self, r_hash: Vec<u8>) {
-        let htlcs = self
-            .0
-            .lightning()
-            .lookup_invoice(fedimint_tonic_lnd::lnrpc::PaymentHash {
-                r_hash,
-                ..Default::default()
-            })
-            .await
-            .unwrap()
-            .into_inner()
-            .htlcs;
-        for htlc in htlcs {
-            if htlc.incoming_endorsed {
-                println!("HTLC endorsed!!");
-            } else {
-                println!("not endorsed");
-            }
-        }
-    }
-
-    async fn connect_peer(&mut self, pubkey: String, host: String) {
-        use fedimint_tonic_lnd::lnrpc::LightningAddress;
-        let _ = self
-            .0
-            .lightning()
-            .connect_peer(fedimint_tonic_lnd::lnrpc::ConnectPeerRequest {
-                addr: Some(LightningAddress { pubkey, host }),
-                ..Default::default()
-            })
-            .await;
-    }
+/// Loads the scenario at `path`, exiting if it is invalid.
+fn load_scenario(path: &str) -> scenario::Scenario {
+    scenario::Scenario::load(path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        std::process::exit(1);
+    })
+}
 
-    async fn new_address(&mut self) -> String {
-        self.0
-            .lightning()
-            .new_address(fedimint_tonic_lnd::lnrpc::NewAddressRequest {
-                ..Default::default()
-            })
-            .await
-            .unwrap()
-            .into_inner()
-            .address
+/// Runs a scenario against a simulated target with three peers instead of the LND nodes.
+async fn simulate(path: &str) {
+    let scenario = load_scenario(path);
+    let sim = sim::Simulator::new();
+    let target = sim::pubkey("target");
+    for i in 0..3 {
+        sim.add_channel(
+            &target,
+            &sim::pubkey(&format!("peer{}", i)),
+            1_000_000,
+            500_000,
+        );
     }
+    sim.fund(&sim::pubkey("alice"), 10_000_000);
+    sim.fund(&sim::pubkey("bob"), 10_000_000);
+    let nodes = [
+        (String::from("alice"), sim.node(&sim::pubkey("alice"))),
+        (String::from("bob"), sim.node(&sim::pubkey("bob"))),
+    ];
+    let results = scenario::run(
+        scenario,
+        nodes.into_iter().collect(),
+        target,
+        None,
+        None,
+        traffic::TrafficStats::default(),
+        timeline::Timeline::start(),
+    )
+    .await;
+    std::process::exit(if results.passed { 0 } else { 1 });
+}
 
-    async fn cancel_invoice(&mut self, payment_hash: Vec<u8>) {
-        let _res = self
-            .0
-            .invoices()
-            .cancel_invoice(fedimint_tonic_lnd::invoicesrpc::CancelInvoiceMsg { payment_hash })
-            .await
-            .unwrap()
-            .into_inner();
-        //println!("{:?}", res);
+/// Closes the channels opened by previous runs and sweeps alice's and bob's wallets. Exits with
+/// an error if a channel could not be closed.
+async fn teardown() {
+    let alice = Client::connect(LND_0_RPCSERVER, LND_0_CERT, LND_0_MACAROON).await;
+    let bob = Client::connect(LND_1_RPCSERVER, LND_1_CERT, LND_1_MACAROON).await;
+    let nodes = [(String::from("alice"), alice), (String::from("bob"), bob)];
+    let failed = teardown::teardown(
+        nodes.into_iter().collect(),
+        channel_log::load(CHANNELS_FILE.unwrap_or("channels.csv")),
+        SWEEP_ADDRESS,
+    )
+    .await;
+    if !failed.is_empty() {
+        std::process::exit(1);
     }
 }
 
-fn gen_hash_table(n: usize) -> Vec<([u8; 32], [u8; 32])> {
-    use bitcoin_hashes::sha256;
-    use bitcoin_hashes::Hash;
-    use rand::{thread_rng, Rng};
-    let mut rng = thread_rng();
-    let mut hash_table = Vec::with_capacity(n);
-    for _ in 0..n {
-        let mut preimage = [0u8; 32];
-        rng.fill(&mut preimage[..]);
-        let hash = sha256::Hash::hash(&preimage);
-        hash_table.push((preimage, hash.to_byte_array()));
-    }
-    hash_table
+async fn defend() {
+    let defender = Client::connect(LND_2_RPCSERVER, LND_2_CERT, LND_2_MACAROON).await;
+    let defaults = endorsement::EndorsementRules::default();
+    let config = defender::DefenderConfig {
+        overflow: match DEFENDER_OVERFLOW {
+            Some("resume") => defender::OverflowPolicy::Resume,
+            _ => defender::OverflowPolicy::Fail,
+        },
+        endorsement: endorsement::EndorsementRules {
+            endorse_unendorsed: ENDORSE_UNENDORSED.is_some(),
+            min_reputation_msat: ENDORSEMENT_MIN_REPUTATION_MSAT
+                .map_or(defaults.min_reputation_msat, |s| s.parse().unwrap()),
+            max_amount_msat: ENDORSEMENT_MAX_AMOUNT_MSAT
+                .map_or(defaults.max_amount_msat, |s| s.parse().unwrap()),
+            max_expiry_delta: ENDORSEMENT_MAX_EXPIRY_DELTA
+                .map_or(defaults.max_expiry_delta, |s| s.parse().unwrap()),
+        },
+        ..Default::default()
+    };
+    defender::run_defender(
+        defender,
+        config,
+        DEFENDER_LOG_FILE.unwrap_or("defender.csv"),
+        ENDORSEMENT_LOG_FILE.unwrap_or("endorsement.csv"),
+    )
+    .await;
 }
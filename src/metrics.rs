@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::sync::Mutex;
+
+use fedimint_tonic_lnd::lnrpc::htlc_attempt::HtlcStatus;
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::payment::PaymentStatus;
+use fedimint_tonic_lnd::lnrpc::{Invoice, Payment, PaymentFailureReason};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Counters and gauges of the current run, fed from payment updates and invoice lookups made by
+/// [`crate::Client`].
+struct Registry {
+    target: Option<String>,
+    /// Target channels each in-flight payment has an HTLC on, by payment hash.
+    in_flight: BTreeMap<String, Vec<u64>>,
+    /// Number and total amount (msat) of accepted HTLCs of each held invoice, by payment hash.
+    held: BTreeMap<Vec<u8>, (u64, u64)>,
+    /// (payment hash, htlc id) of invoice HTLCs already counted as endorsed or not.
+    seen_htlcs: BTreeSet<(Vec<u8>, u64)>,
+    endorsed: u64,
+    unendorsed: u64,
+    /// Final payment outcomes by (kind, status, failure reason).
+    payments: BTreeMap<(PaymentKind, &'static str, &'static str), u64>,
+    /// Routing fees paid by succeeded payments, by kind.
+    fees_msat: BTreeMap<PaymentKind, i64>,
+}
+
+/// Whether a payment is part of the jam or honest traffic, kept apart in the payment series.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum PaymentKind {
+    Jam,
+    Honest,
+}
+
+impl PaymentKind {
+    fn label(self) -> &'static str {
+        match self {
+            PaymentKind::Jam => "jam",
+            PaymentKind::Honest => "honest",
+        }
+    }
+}
+
+/// Shared by every run in the process, see the [crate documentation](crate).
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    target: None,
+    in_flight: BTreeMap::new(),
+    held: BTreeMap::new(),
+    seen_htlcs: BTreeSet::new(),
+    endorsed: 0,
+    unendorsed: 0,
+    payments: BTreeMap::new(),
+    fees_msat: BTreeMap::new(),
+});
+
+/// Sets the node whose channels the in-flight HTLC gauge is broken down by.
+pub fn set_target(target: &str) {
+    REGISTRY.lock().unwrap().target = Some(String::from(target));
+}
+
+/// Records an update from a `SendPaymentV2` stream of a `kind` payment.
+pub fn record_payment(payment: &Payment, kind: PaymentKind) {
+    let mut registry = REGISTRY.lock().unwrap();
+    if payment.status == PaymentStatus::Succeeded as i32
+        || payment.status == PaymentStatus::Failed as i32
+    {
+        registry.in_flight.remove(&payment.payment_hash);
+        let status = PaymentStatus::try_from(payment.status)
+            .map_or("UNKNOWN", |status| status.as_str_name());
+        let reason = PaymentFailureReason::try_from(payment.failure_reason)
+            .map_or("UNKNOWN", |reason| reason.as_str_name());
+        *registry.payments.entry((kind, status, reason)).or_default() += 1;
+        if payment.status == PaymentStatus::Succeeded as i32 {
+            *registry.fees_msat.entry(kind).or_default() += payment.fee_msat;
+        }
+        return;
+    }
+
+    let Some(target) = registry.target.clone() else {
+        return;
+    };
+    // the hop to the target arrives on one of its channels, the next one leaves on another
+    let mut channels = Vec::new();
+    for htlc in &payment.htlcs {
+        if htlc.status != HtlcStatus::InFlight as i32 {
+            continue;
+        }
+        let hops = htlc.route.as_ref().map_or(&[][..], |route| &route.hops[..]);
+        for (i, hop) in hops.iter().enumerate() {
+            if hop.pub_key == target {
+                channels.push(hop.chan_id);
+                if let Some(next) = hops.get(i + 1) {
+                    channels.push(next.chan_id);
+                }
+            }
+        }
+    }
+    registry
+        .in_flight
+        .insert(payment.payment_hash.clone(), channels);
+}
+
+/// Records an invoice returned by `LookupInvoice` or `SubscribeInvoices`.
+pub fn record_invoice(invoice: &Invoice) {
+    let mut registry = REGISTRY.lock().unwrap();
+    for htlc in &invoice.htlcs {
+        if registry
+            .seen_htlcs
+            .insert((invoice.r_hash.clone(), htlc.htlc_index))
+        {
+            if htlc.incoming_endorsed {
+                registry.endorsed += 1;
+            } else {
+                registry.unendorsed += 1;
+            }
+        }
+    }
+    if invoice.state == InvoiceState::Accepted as i32 {
+        let amount_msat = invoice.htlcs.iter().map(|htlc| htlc.amt_msat).sum();
+        registry.held.insert(
+            invoice.r_hash.clone(),
+            (invoice.htlcs.len() as u64, amount_msat),
+        );
+    } else {
+        registry.held.remove(&invoice.r_hash);
+    }
+}
+
+/// Prometheus text exposition of the registry.
+fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    let mut per_channel: BTreeMap<u64, u64> = BTreeMap::new();
+    for chan_id in registry.in_flight.values().flatten() {
+        *per_channel.entry(*chan_id).or_default() += 1;
+    }
+    out.push_str(
+        "# HELP jammy_target_htlcs_in_flight Our in-flight HTLCs on each target channel.\n",
+    );
+    out.push_str("# TYPE jammy_target_htlcs_in_flight gauge\n");
+    for (chan_id, count) in per_channel {
+        writeln!(
+            out,
+            "jammy_target_htlcs_in_flight{{chan_id=\"{}\"}} {}",
+            chan_id, count
+        )
+        .unwrap();
+    }
+
+    let (slots, locked_msat) = registry
+        .held
+        .values()
+        .fold((0, 0), |(slots, locked), (htlcs, msat)| {
+            (slots + htlcs, locked + msat)
+        });
+    out.push_str("# HELP jammy_slots_held HTLCs held by our receivers.\n");
+    out.push_str("# TYPE jammy_slots_held gauge\n");
+    writeln!(out, "jammy_slots_held {}", slots).unwrap();
+    out.push_str("# HELP jammy_sats_locked Sats locked in HTLCs held by our receivers.\n");
+    out.push_str("# TYPE jammy_sats_locked gauge\n");
+    writeln!(out, "jammy_sats_locked {}", locked_msat / 1000).unwrap();
+
+    out.push_str("# HELP jammy_htlcs_received_total HTLCs received by our invoices.\n");
+    out.push_str("# TYPE jammy_htlcs_received_total counter\n");
+    writeln!(
+        out,
+        "jammy_htlcs_received_total{{endorsed=\"true\"}} {}",
+        registry.endorsed
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "jammy_htlcs_received_total{{endorsed=\"false\"}} {}",
+        registry.unendorsed
+    )
+    .unwrap();
+
+    out.push_str("# HELP jammy_payments_total Payments that reached a final state, by kind.\n");
+    out.push_str("# TYPE jammy_payments_total counter\n");
+    for ((kind, status, reason), count) in &registry.payments {
+        writeln!(
+            out,
+            "jammy_payments_total{{kind=\"{}\",status=\"{}\",reason=\"{}\"}} {}",
+            kind.label(),
+            status,
+            reason,
+            count
+        )
+        .unwrap();
+    }
+
+    out.push_str(
+        "# HELP jammy_fees_spent_msat_total Routing fees paid by succeeded payments, by kind.\n",
+    );
+    out.push_str("# TYPE jammy_fees_spent_msat_total counter\n");
+    for (kind, fees_msat) in &registry.fees_msat {
+        writeln!(
+            out,
+            "jammy_fees_spent_msat_total{{kind=\"{}\"}} {}",
+            kind.label(),
+            fees_msat
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Background task serving the metrics on `http://<addr>/metrics`.
+pub fn spawn_metrics_server(addr: &str) -> tokio::task::JoinHandle<()> {
+    let listener = std::net::TcpListener::bind(addr).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    log!("serving metrics on http://{}/metrics", addr);
+
+    tokio::task::spawn(async move {
+        let listener = TcpListener::from_std(listener).unwrap();
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                continue;
+            };
+            tokio::task::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = if request.starts_with(b"GET /metrics ") {
+                    let body = render();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    String::from(
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    })
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fedimint_tonic_lnd::routerrpc::{MissionControlConfig, PairData, PairHistory};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::LightningBackend;
+use crate::probe::LiquidityTable;
+
+/// Mission control state of a node, taken before a run changes it.
+pub struct Snapshot {
+    pub pairs: Vec<PairHistory>,
+    pub config: MissionControlConfig,
+}
+
+/// Mission control settings to run with, unset ones keep the node's current value.
+#[derive(Deserialize, Clone, Default)]
+pub struct Tuning {
+    /// Time for a penalized pair to recover half of its success probability.
+    pub half_life_seconds: Option<u64>,
+    /// Success probability of pairs without history.
+    pub hop_probability: Option<f32>,
+    /// Importance of the history against `hop_probability`, between 0 and 1.
+    pub weight: Option<f32>,
+    pub maximum_payment_results: Option<u32>,
+    pub minimum_failure_relax_interval: Option<u64>,
+}
+
+impl Tuning {
+    pub fn is_empty(&self) -> bool {
+        self.half_life_seconds.is_none()
+            && self.hop_probability.is_none()
+            && self.weight.is_none()
+            && self.maximum_payment_results.is_none()
+            && self.minimum_failure_relax_interval.is_none()
+    }
+
+    /// The full config `SetMissionControlConfig` requires, with these settings over `config`.
+    pub fn apply(&self, config: &MissionControlConfig) -> MissionControlConfig {
+        MissionControlConfig {
+            half_life_seconds: self.half_life_seconds.unwrap_or(config.half_life_seconds),
+            hop_probability: self.hop_probability.unwrap_or(config.hop_probability),
+            weight: self.weight.unwrap_or(config.weight),
+            maximum_payment_results: self
+                .maximum_payment_results
+                .unwrap_or(config.maximum_payment_results),
+            minimum_failure_relax_interval: self
+                .minimum_failure_relax_interval
+                .unwrap_or(config.minimum_failure_relax_interval),
+        }
+    }
+}
+
+pub async fn snapshot<B: LightningBackend>(node: &mut B) -> Snapshot {
+    Snapshot {
+        pairs: node.query_mission_control().await,
+        config: node.mission_control_config().await,
+    }
+}
+
+/// Puts mission control back as it was in `snapshot`, dropping what was learned since.
+pub async fn restore<B: LightningBackend>(node: &mut B, snapshot: Snapshot) {
+    node.reset_mission_control().await;
+    if !snapshot.pairs.is_empty() {
+        node.import_mission_control(snapshot.pairs, true).await;
+    }
+    node.set_mission_control_config(snapshot.config).await;
+}
+
+/// Records a recent success over both directions of every channel of `target`, so that
+/// pathfinding prefers routes through it. The amount is the lower end of the channel
+/// direction's estimate in `liquidity` when probed, its capacity otherwise. Returns how many
+/// pairs were imported.
+pub async fn seed_target<B: LightningBackend>(
+    node: &mut B,
+    target: &str,
+    liquidity: &LiquidityTable,
+) -> usize {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    // largest amount per pair, as parallel channels share one
+    let mut amounts: BTreeMap<(String, String), i64> = BTreeMap::new();
+    for edge in node.graph_get_node_channels(String::from(target)).await {
+        for (from, to) in [
+            (&edge.node1_pub, &edge.node2_pub),
+            (&edge.node2_pub, &edge.node1_pub),
+        ] {
+            let amount_sat = liquidity
+                .get(&(edge.channel_id, from.clone()))
+                .map_or(edge.capacity, |estimate| estimate.min_sat);
+            let known = amounts.entry((from.clone(), to.clone())).or_default();
+            *known = (*known).max(amount_sat);
+        }
+    }
+    let pairs: Vec<PairHistory> = amounts
+        .into_iter()
+        .filter(|(_, amount_sat)| *amount_sat > 0)
+        .map(|((from, to), amount_sat)| PairHistory {
+            node_from: hex::decode(from).unwrap(),
+            node_to: hex::decode(to).unwrap(),
+            history: Some(PairData {
+                success_time: now,
+                success_amt_sat: amount_sat,
+                success_amt_msat: amount_sat * 1000,
+                ..Default::default()
+            }),
+        })
+        .collect();
+    let count = pairs.len();
+    node.import_mission_control(pairs, true).await;
+    count
+}
+
+/// A [`Snapshot`] as kept on disk, pubkeys in hex.
+#[derive(Serialize, Deserialize)]
+struct SavedSnapshot {
+    node: String,
+    half_life_seconds: u64,
+    hop_probability: f32,
+    weight: f32,
+    maximum_payment_results: u32,
+    minimum_failure_relax_interval: u64,
+    #[serde(default)]
+    pairs: Vec<SavedPair>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPair {
+    from: String,
+    to: String,
+    fail_time: i64,
+    fail_amt_msat: i64,
+    success_time: i64,
+    success_amt_msat: i64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SavedSnapshots {
+    #[serde(default)]
+    snapshot: Vec<SavedSnapshot>,
+}
+
+/// Writes `snapshots` to `path`, replacing what it held, so that a run that does
+/// not get to restore them leaves them for [`load`].
+pub fn save(path: &str, snapshots: &[(String, Snapshot)]) {
+    let saved = SavedSnapshots {
+        snapshot: snapshots
+            .iter()
+            .map(|(node, snapshot)| SavedSnapshot {
+                node: node.clone(),
+                half_life_seconds: snapshot.config.half_life_seconds,
+                hop_probability: snapshot.config.hop_probability,
+                weight: snapshot.config.weight,
+                maximum_payment_results: snapshot.config.maximum_payment_results,
+                minimum_failure_relax_interval: snapshot.config.minimum_failure_relax_interval,
+                pairs: snapshot
+                    .pairs
+                    .iter()
+                    .map(|pair| {
+                        let history = pair.history.clone().unwrap_or_default();
+                        SavedPair {
+                            from: hex::encode(&pair.node_from),
+                            to: hex::encode(&pair.node_to),
+                            fail_time: history.fail_time,
+                            fail_amt_msat: history.fail_amt_msat,
+                            success_time: history.success_time,
+                            success_amt_msat: history.success_amt_msat,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    std::fs::write(path, toml::to_string(&saved).unwrap()).unwrap();
+}
+
+/// Reads back the (node, snapshot) pairs saved to `path`. A missing file has none.
+pub fn load(path: &str) -> Vec<(String, Snapshot)> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let saved: SavedSnapshots = toml::from_str(&contents).unwrap();
+    saved
+        .snapshot
+        .into_iter()
+        .map(|saved| {
+            let pairs = saved
+                .pairs
+                .into_iter()
+                .map(|pair| PairHistory {
+                    node_from: hex::decode(pair.from).unwrap(),
+                    node_to: hex::decode(pair.to).unwrap(),
+                    history: Some(PairData {
+                        fail_time: pair.fail_time,
+                        fail_amt_sat: pair.fail_amt_msat / 1000,
+                        fail_amt_msat: pair.fail_amt_msat,
+                        success_time: pair.success_time,
+                        success_amt_sat: pair.success_amt_msat / 1000,
+                        success_amt_msat: pair.success_amt_msat,
+                    }),
+                })
+                .collect();
+            let config = MissionControlConfig {
+                half_life_seconds: saved.half_life_seconds,
+                hop_probability: saved.hop_probability,
+                weight: saved.weight,
+                maximum_payment_results: saved.maximum_payment_results,
+                minimum_failure_relax_interval: saved.minimum_failure_relax_interval,
+            };
+            (saved.node, Snapshot { pairs, config })
+        })
+        .collect()
+}
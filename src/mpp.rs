@@ -0,0 +1,49 @@
+//! Splitting jam payments into shards, so that one hold invoice occupies HTLC slots on several
+//! parallel channels of the target.
+//!
+//! LND only splits a payment when no single route can carry it, and with non-strict forwarding
+//! the target forwards each shard over whichever parallel channel it picks, not the one in our
+//! route. `jammy simulate` always splits into `max_parts` and spreads the shards over the least
+//! used parallel channels, so the spread it reports is the best case. A regtest run shows what a
+//! real target does.
+
+use std::collections::BTreeMap;
+
+use fedimint_tonic_lnd::lnrpc::Route;
+use serde::Deserialize;
+
+/// How a hold invoice payment is split into shards. AMP is not an option: hold invoices can't be
+/// paid with AMP, see [`crate::spontaneous`] for AMP payments.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Split {
+    /// Most shards the payment may be split into.
+    pub max_parts: u32,
+    /// Largest shard, 0 to let LND decide.
+    #[serde(default)]
+    pub max_shard_size_msat: u64,
+}
+
+/// Shards of the given routes per channel of `target` they went through, into or out of it.
+pub fn shard_distribution(target: &str, routes: &[Route]) -> BTreeMap<u64, usize> {
+    let mut shards = BTreeMap::new();
+    for route in routes {
+        for (i, hop) in route.hops.iter().enumerate() {
+            if hop.pub_key != target {
+                continue;
+            }
+            *shards.entry(hop.chan_id).or_default() += 1;
+            if let Some(next) = route.hops.get(i + 1) {
+                *shards.entry(next.chan_id).or_default() += 1;
+            }
+        }
+    }
+    shards
+}
+
+pub fn print_distribution(shards: &BTreeMap<u64, usize>) {
+    log!("shards per channel of the target:");
+    for (chan_id, count) in shards {
+        log!("  {}: {}", chan_id, count);
+    }
+}
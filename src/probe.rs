@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use fedimint_tonic_lnd::lnrpc::failure::FailureCode;
+use fedimint_tonic_lnd::lnrpc::ChannelEdge;
+
+use crate::backend::LightningBackend;
+
+/// Probing stops once the liquidity of a channel direction is known to this many sats.
+const RESOLUTION_SAT: i64 = 1_000;
+
+/// What is known of the balance one node of a channel can send to the other.
+#[derive(Clone, Debug)]
+pub struct LiquidityEstimate {
+    pub chan_id: u64,
+    pub from: String,
+    pub to: String,
+    pub capacity: i64,
+    /// Largest amount seen getting through, in sats.
+    pub min_sat: i64,
+    /// Largest amount that may get through, in sats.
+    pub max_sat: i64,
+    pub probes: usize,
+}
+
+/// Liquidity estimates keyed by channel id and sending node.
+pub type LiquidityTable = HashMap<(u64, String), LiquidityEstimate>;
+
+/// How a probe through the probed channel came back.
+enum Outcome {
+    /// Reached the destination, which rejected the unknown payment hash.
+    Passed,
+    /// The sending node of the probed channel could not forward it.
+    Blocked,
+    /// A channel before the probed one could not carry it.
+    Upstream,
+    /// Failed anywhere else or for any other reason, telling nothing about the channel.
+    Inconclusive(String),
+}
+
+/// Pubkeys of the nodes between us and `node`, avoiding `avoid`: none if we have a channel with
+/// it, else one of our peers that has.
+async fn route_to<B: LightningBackend>(
+    prober: &mut B,
+    our_peers: &HashSet<String>,
+    node: &str,
+    avoid: &str,
+) -> Option<Vec<String>> {
+    if our_peers.contains(node) {
+        return Some(Vec::new());
+    }
+    let peers = prober.graph_get_node_peers(String::from(node)).await;
+    peers
+        .into_iter()
+        .find(|peer| peer != avoid && our_peers.contains(peer))
+        .map(|peer| vec![peer])
+}
+
+/// Probes `amount_sat` from `hops[len - 2]` to the last hop, its destination.
+async fn probe<B: LightningBackend>(prober: &mut B, hops: &[String], amount_sat: i64) -> Outcome {
+    let failure = match prober.probe_route(hops.to_vec(), amount_sat * 1000).await {
+        Ok(failure) => failure,
+        Err(err) => return Outcome::Inconclusive(err),
+    };
+    // the source index counts us as 0
+    let destination = hops.len() as u32;
+    let code = FailureCode::try_from(failure.code).unwrap_or(FailureCode::Reserved);
+    match (failure.failure_source_index, code) {
+        (index, FailureCode::IncorrectOrUnknownPaymentDetails) if index == destination => {
+            Outcome::Passed
+        }
+        (index, FailureCode::TemporaryChannelFailure) if index == destination - 1 => {
+            Outcome::Blocked
+        }
+        (index, FailureCode::TemporaryChannelFailure) if index < destination - 1 => {
+            Outcome::Upstream
+        }
+        (index, code) => Outcome::Inconclusive(format!("{:?} at hop {}", code, index)),
+    }
+}
+
+/// Binary-searches the amount `from` can send to `to` over `edge`, within `max_probes` probes.
+async fn probe_direction<B: LightningBackend>(
+    prober: &mut B,
+    our_peers: &HashSet<String>,
+    edge: &ChannelEdge,
+    from: &str,
+    to: &str,
+    max_probes: usize,
+) -> Option<LiquidityEstimate> {
+    let Some(mut hops) = route_to(prober, our_peers, from, to).await else {
+        log!("no route to {} avoiding {}, not probing", from, to);
+        return None;
+    };
+    hops.push(String::from(from));
+    hops.push(String::from(to));
+
+    let policy = if edge.node1_pub == from {
+        &edge.node1_policy
+    } else {
+        &edge.node2_policy
+    };
+    let max_htlc_sat = policy
+        .as_ref()
+        .map(|policy| (policy.max_htlc_msat / 1000) as i64)
+        .filter(|max_htlc| *max_htlc > 0)
+        .unwrap_or(edge.capacity);
+    let mut estimate = LiquidityEstimate {
+        chan_id: edge.channel_id,
+        from: String::from(from),
+        to: String::from(to),
+        capacity: edge.capacity,
+        min_sat: 0,
+        max_sat: edge.capacity.min(max_htlc_sat),
+        probes: 0,
+    };
+    // largest amount the channels leading to the probed one are not known to block
+    let mut ceiling = estimate.max_sat;
+    while estimate.probes < max_probes && ceiling - estimate.min_sat > RESOLUTION_SAT {
+        let amount = (estimate.min_sat + ceiling + 1) / 2;
+        estimate.probes += 1;
+        match probe(prober, &hops, amount).await {
+            Outcome::Passed => estimate.min_sat = amount,
+            Outcome::Blocked => {
+                estimate.max_sat = amount - 1;
+                ceiling = estimate.max_sat;
+            }
+            Outcome::Upstream => ceiling = amount - 1,
+            Outcome::Inconclusive(reason) => {
+                log!(
+                    "probe of {} sat over {} inconclusive ({}), stopping",
+                    amount,
+                    edge.channel_id,
+                    reason
+                );
+                break;
+            }
+        }
+    }
+    Some(estimate)
+}
+
+/// Estimates the liquidity in both directions of every channel of `target` by sending probes
+/// from `prober` to random payment hashes. Each probe goes through one of our peers to the
+/// sending side of the channel, then over the channel to its destination, and the amount is
+/// binary-searched between what reached the destination and what the sending node failed to
+/// forward. Probes also have to fit in the channels leading to the probed one, amounts those
+/// block are not searched further, leaving the upper end of the estimate where it was.
+///
+/// LND forwards over any channel with the next node, so with parallel channels the estimate is
+/// that of the pair.
+pub async fn probe_target<B: LightningBackend>(
+    prober: &mut B,
+    target: &str,
+    max_probes: usize,
+) -> LiquidityTable {
+    let us = prober.get_pubkey().await;
+    let our_peers: HashSet<String> = prober
+        .graph_get_node_peers(us.clone())
+        .await
+        .into_iter()
+        .collect();
+    let mut table = LiquidityTable::new();
+    for edge in prober.graph_get_node_channels(String::from(target)).await {
+        let peer = if edge.node1_pub == target {
+            edge.node2_pub.clone()
+        } else {
+            edge.node1_pub.clone()
+        };
+        if peer == us {
+            continue;
+        }
+        for (from, to) in [(target, peer.as_str()), (peer.as_str(), target)] {
+            if let Some(estimate) =
+                probe_direction(prober, &our_peers, &edge, from, to, max_probes).await
+            {
+                table.insert((estimate.chan_id, estimate.from.clone()), estimate);
+            }
+        }
+    }
+    table
+}
+
+pub fn print_estimates(table: &LiquidityTable) {
+    let sorted: BTreeMap<_, _> = table.iter().collect();
+    for ((chan_id, from), estimate) in sorted {
+        log!(
+            "{} from {}: {}..{} of {} sat ({} probes)",
+            chan_id,
+            from,
+            estimate.min_sat,
+            estimate.max_sat,
+            estimate.capacity,
+            estimate.probes
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Custom TLV records attached to jamming payments, to experiment with signals beyond
+//! `endorsed`.
+//!
+//! Records are sent to the receiver as `dest_custom_records` and show up in the
+//! `custom_records` of its invoice HTLCs. The LND fork jammy is built against has no
+//! `first_hop_custom_records` in `SendPaymentRequest`, so records on the `update_add_htlc` to
+//! our peer cannot be set yet. Spontaneous payments are held by a hop before their destination,
+//! see [`crate::spontaneous`], so their receiver never sees the records.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::spontaneous::KEYSEND_RECORD;
+
+/// Lowest custom record type, LND rejects payments with lower ones.
+pub const MIN_CUSTOM_RECORD: u64 = 65536;
+
+/// Custom records by type, as in `SendPaymentRequest` and `InvoiceHTLC`.
+pub type CustomRecords = HashMap<u64, Vec<u8>>;
+
+fn record(key: &str, value: &str) -> Result<(u64, Vec<u8>), String> {
+    let key: u64 = key
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid custom record type {}", key))?;
+    if key < MIN_CUSTOM_RECORD || key == KEYSEND_RECORD {
+        return Err(format!("custom record type {} is reserved", key));
+    }
+    let value =
+        hex::decode(value.trim()).map_err(|_| format!("invalid custom record value {}", value))?;
+    Ok((key, value))
+}
+
+/// Parses records written as `type=hex value` pairs separated by commas, such as
+/// `65537=01,65539=cafe`. Each type may appear once.
+pub fn parse(records: &str) -> Result<CustomRecords, String> {
+    let mut parsed = CustomRecords::new();
+    for pair in records.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("custom record {} is not type=value", pair))?;
+        let (key, value) = record(key, value)?;
+        if parsed.insert(key, value).is_some() {
+            return Err(format!("custom record type {} is repeated", key));
+        }
+    }
+    Ok(parsed)
+}
+
+/// Deserializes records from a table of hex values by type, such as
+/// `custom_records = { 65537 = "01" }`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CustomRecords, D::Error> {
+    let table = BTreeMap::<String, String>::deserialize(deserializer)?;
+    table
+        .iter()
+        .map(|(key, value)| record(key, value))
+        .collect::<Result<_, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Records sorted by type, as space separated `type=hex value` pairs.
+pub fn format(records: &CustomRecords) -> String {
+    let sorted: BTreeMap<_, _> = records.iter().collect();
+    sorted
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, hex::encode(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_parsed() {
+        let records = parse(" 65537=01, 65539=CAFE,").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[&65537], vec![0x01]);
+        assert_eq!(records[&65539], vec![0xca, 0xfe]);
+        assert_eq!(format(&records), "65537=01 65539=cafe");
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_records_are_rejected() {
+        let keysend = format!("{}=01", KEYSEND_RECORD);
+        for invalid in [
+            "65535=01",
+            "0=01",
+            keysend.as_str(),
+            "65537=0g",
+            "65537=012",
+            "x=01",
+            "65537",
+            "65537=01,65537=02",
+        ] {
+            assert!(parse(invalid).is_err(), "{} accepted", invalid);
+        }
+        assert!(parse("65536=").is_ok());
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::timeline::{unix_ms, Timeline};
+use crate::Client;
+
+const HEADER: &str = "unix_ms,elapsed_ms,phase,source,node,chan_id,peer,capacity,disabled,\
+fee_base_msat,fee_rate_milli_msat,time_lock_delta,max_htlc_msat,\
+local_balance,remote_balance,pending_htlcs,num_updates";
+
+/// Background task that polls the target's channels (policy, disabled flag, capacity) and our
+/// own channels (balances, pending htlcs, update count) every `every`, appending one CSV row
+/// per channel to `path`.
+pub fn spawn_sampler(
+    mut graph: Client,
+    mut own: Vec<(&'static str, Client)>,
+    target: String,
+    path: &str,
+    every: Duration,
+    timeline: Timeline,
+) -> tokio::task::JoinHandle<()> {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    writeln!(out, "{}", HEADER).unwrap();
+
+    tokio::task::spawn(async move {
+        let mut ticker = interval(every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let now = unix_ms();
+            let elapsed = timeline.elapsed_ms();
+            let phase = timeline.phase();
+
+            for edge in graph.graph_get_node_channels(target.clone()).await {
+                let (policy, peer) = if edge.node1_pub == target {
+                    (edge.node1_policy, edge.node2_pub)
+                } else {
+                    (edge.node2_policy, edge.node1_pub)
+                };
+                let policy = policy.unwrap_or_default();
+                writeln!(
+                    out,
+                    "{},{},{},target,{},{},{},{},{},{},{},{},{},,,,",
+                    now,
+                    elapsed,
+                    phase,
+                    target,
+                    edge.channel_id,
+                    peer,
+                    edge.capacity,
+                    policy.disabled,
+                    policy.fee_base_msat,
+                    policy.fee_rate_milli_msat,
+                    policy.time_lock_delta,
+                    policy.max_htlc_msat,
+                )
+                .unwrap();
+            }
+
+            for (name, client) in own.iter_mut() {
+                for channel in client.list_channels().await {
+                    writeln!(
+                        out,
+                        "{},{},{},own,{},{},{},{},{},,,,,{},{},{},{}",
+                        now,
+                        elapsed,
+                        phase,
+                        name,
+                        channel.chan_id,
+                        channel.remote_pubkey,
+                        channel.capacity,
+                        !channel.active,
+                        channel.local_balance,
+                        channel.remote_balance,
+                        channel.pending_htlcs.len(),
+                        channel.num_updates,
+                    )
+                    .unwrap();
+                }
+            }
+            out.flush().unwrap();
+        }
+    })
+}
@@ -0,0 +1,718 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use serde::Deserialize;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::{sleep, Duration};
+
+use crate::backend::{node, LightningBackend};
+use crate::channel_log::ChannelLog;
+use crate::channel_point::ChannelPoint;
+use crate::events::{self, Event};
+use crate::failures::{self, FailureTable};
+use crate::gen_hash_table;
+use crate::mission_control::{self, Tuning};
+use crate::mpp::{self, Split};
+use crate::probe::{self, LiquidityTable};
+use crate::records::{self, CustomRecords};
+use crate::setup::{self, ChannelOptions, ChannelRequest};
+use crate::spontaneous::PaymentKind;
+use crate::strategy::{
+    self, CircularJam, FastJam, JamHtlc, JamState, LiquidityJam, PaymentOptions, SlotJam,
+    StrategyEvent, CONTROLS,
+};
+use crate::timeline::Timeline;
+use crate::traffic::{self, AmountDistribution, TrafficStats};
+
+/// A reproducible experiment: phases run in order, then assertions are checked on the results.
+///
+/// ```toml
+/// name = "slot jam"
+///
+/// [[phase]]
+/// kind = "setup"
+/// channels = [{ node = "alice", peer_index = 1, amount = 500000, private = true }]
+///
+/// [[phase]]
+/// kind = "jam"
+/// mode = "slots"
+/// sender = "alice"
+/// receiver = "bob"
+///
+/// [[assert]]
+/// metric = "honest_failure_rate"
+/// phase = "jam"
+/// min = 0.8
+/// ```
+#[derive(Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    /// Overrides the honest traffic rate and amount distribution.
+    pub traffic: Option<TrafficSection>,
+    #[serde(rename = "phase")]
+    phases: Vec<Phase>,
+    #[serde(rename = "assert", default)]
+    assertions: Vec<Assertion>,
+    /// Where the mission control snapshots of the run are kept until they are restored, so that
+    /// the next run restores them if this one does not finish. Set by the caller, kept in memory
+    /// only if unset.
+    #[serde(skip)]
+    pub mission_control_file: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TrafficSection {
+    #[serde(default, deserialize_with = "traffic::deserialize_rate")]
+    pub rate: Option<f64>,
+    /// See [`AmountDistribution::parse`].
+    #[serde(default, deserialize_with = "traffic::deserialize_amount")]
+    pub amount: Option<AmountDistribution>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Phase {
+    /// Opens channels from our nodes to the target's peers, unless they already exist, and waits
+    /// for them to confirm. Fails before opening anything if a node can't afford its channels or
+    /// a channel names a peer the target does not have.
+    Setup {
+        channels: Vec<ChannelSpec>,
+        /// Moves on-chain funds between our nodes to cover their shortfalls first.
+        #[serde(default)]
+        rebalance: bool,
+    },
+    /// Sends payments that are settled after `hold_secs`, earning the target fees.
+    BuildReputation {
+        sender: String,
+        receiver: String,
+        payments: usize,
+        amount: i64,
+        #[serde(default = "default_hold_secs")]
+        hold_secs: u64,
+    },
+    /// Runs a jamming strategy. Payments it leaves held are kept until the next release phase.
+    Jam {
+        mode: JamMode,
+        sender: String,
+        /// Not used by circular jams, which pay the sender itself.
+        receiver: Option<String>,
+        /// HTLCs sent, per tick for fast jams.
+        htlcs: Option<usize>,
+        amount: Option<i64>,
+        /// How many ticks fast jams keep sending for.
+        ticks: Option<u64>,
+        #[serde(default = "default_tick_ms")]
+        tick_ms: u64,
+        /// Hold invoices by default, or spontaneous payments held by the receiver's HTLC
+        /// interceptor. The receiver holds spontaneous payments as forwards to a node that does
+        /// not exist, not as a keysend or AMP receiver would, see [`crate::spontaneous`].
+        #[serde(default)]
+        payment: PaymentKind,
+        /// Pays each hold invoice in shards, to spread it over parallel channels. The channels
+        /// the shards were held over are reported after the phase, see [`crate::mpp`].
+        split: Option<Split>,
+        /// Custom records sent to the receiver with every payment, hex values by type, see
+        /// [`crate::records`].
+        #[serde(default, deserialize_with = "records::deserialize")]
+        custom_records: CustomRecords,
+    },
+    /// Estimates the liquidity of the target's channels by probing them from `node`. The
+    /// estimates are handed to the strategies of later jam phases.
+    Probe {
+        node: String,
+        /// Probes sent per channel direction at most.
+        #[serde(default = "default_max_probes")]
+        max_probes: usize,
+    },
+    /// Prepares the mission control of `nodes` for the next phases. Each node's mission control
+    /// is snapshotted the first time and restored once the scenario ends, or by the next run
+    /// if it does not end, see [`Scenario::mission_control_file`].
+    MissionControl {
+        nodes: Vec<String>,
+        /// Forgets every pair history.
+        #[serde(default)]
+        reset: bool,
+        /// Records successes over the target's channels so pathfinding prefers them, see
+        /// [`mission_control::seed_target`].
+        #[serde(default)]
+        seed: bool,
+        /// `half_life_seconds`, `hop_probability`, `weight` and the rest of [`Tuning`].
+        #[serde(flatten)]
+        tuning: Tuning,
+    },
+    /// Waits for blocks to be mined. Counts as part of the jam while jam HTLCs are held, so that
+    /// honest payments sent during the hold are measured under the `jam` phase.
+    WaitBlocks { blocks: u32 },
+    /// Cancels every invoice held by the jam phases.
+    Release,
+    /// Stops the honest traffic, waits for the payments in flight to resolve and prints the
+    /// results so far.
+    Report,
+}
+
+#[derive(Deserialize)]
+struct ChannelSpec {
+    node: String,
+    /// Index into the target's peers, as returned by the graph. The setup phase fails before
+    /// opening anything if the target has no such peer.
+    peer_index: usize,
+    amount: i64,
+    #[serde(default)]
+    push: i64,
+    /// `private`, `commitment_type`, fees and the other [`ChannelOptions`].
+    #[serde(flatten)]
+    options: ChannelOptions,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum JamMode {
+    /// Many small HTLCs exhausting the target's HTLC slots.
+    Slots,
+    /// Few large HTLCs locking up the target's liquidity.
+    Liquidity,
+    /// HTLCs from the sender back to itself through the target.
+    Circular,
+    /// HTLCs released as soon as they are held, sent again every tick.
+    Fast,
+}
+
+#[derive(Deserialize)]
+struct Assertion {
+    metric: Metric,
+    /// Restricts honest payment metrics to payments sent during this phase. `jam` covers the jam
+    /// phases and the blocks waited while their HTLCs are held.
+    phase: Option<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Metric {
+    HonestPayments,
+    HonestFailureRate,
+    JamHtlcsSent,
+    JamHtlcsHeld,
+}
+
+fn default_hold_secs() -> u64 {
+    3
+}
+
+fn default_tick_ms() -> u64 {
+    1000
+}
+
+fn default_max_probes() -> usize {
+    16
+}
+
+/// Why a scenario could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Read(std::io::Error),
+    Parse(toml::de::Error),
+    /// Parsed, but a phase lacks what it needs to run.
+    Invalid(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Read(err) => write!(f, "cannot read scenario: {}", err),
+            LoadError::Parse(err) => write!(f, "invalid scenario: {}", err),
+            LoadError::Invalid(reason) => write!(f, "invalid scenario: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl Scenario {
+    /// Reads the scenario at `path`.
+    pub fn load(path: &str) -> Result<Self, LoadError> {
+        let contents = std::fs::read_to_string(path).map_err(LoadError::Read)?;
+        Self::parse(&contents)
+    }
+
+    /// Parses a scenario written in TOML, and checks that its jam phases have the receiver and
+    /// amount their mode needs.
+    pub fn parse(contents: &str) -> Result<Self, LoadError> {
+        let scenario: Self = toml::from_str(contents).map_err(LoadError::Parse)?;
+        for (i, phase) in scenario.phases.iter().enumerate() {
+            if let Phase::Jam {
+                mode,
+                receiver,
+                amount,
+                ..
+            } = phase
+            {
+                if receiver.is_none() && !matches!(mode, JamMode::Circular) {
+                    return Err(LoadError::Invalid(format!(
+                        "jam phase {} needs a receiver",
+                        i + 1
+                    )));
+                }
+                if amount.is_none() && matches!(mode, JamMode::Liquidity) {
+                    return Err(LoadError::Invalid(format!(
+                        "liquidity jam phase {} needs an amount",
+                        i + 1
+                    )));
+                }
+            }
+        }
+        Ok(scenario)
+    }
+}
+
+/// Outcomes of the jam phases.
+#[derive(Default)]
+pub struct JamResults {
+    /// Jam HTLCs sent, including ones re-sent or released by the strategy.
+    pub sent: u64,
+    /// Jam HTLCs still held by the receiver when released.
+    pub held: u64,
+    /// Shards of split payments per channel of the target they went through.
+    pub shards: BTreeMap<u64, usize>,
+    /// Failures of jam HTLC attempts at the target, see [`failures::tally`].
+    pub failures: FailureTable,
+    /// Payments still held.
+    holding: Vec<JamHtlc>,
+}
+
+/// A channel of one of our nodes used by the scenario.
+pub struct SetupChannel {
+    pub node: String,
+    pub point: ChannelPoint,
+    /// Short channel id once confirmed, as used by the graph and HTLC events.
+    pub id: Option<u64>,
+}
+
+/// What a scenario run produced. Honest payment outcomes are in the [`TrafficStats`] passed to
+/// [`run`].
+pub struct ScenarioResults {
+    /// Channels of the setup phases.
+    pub channels: Vec<SetupChannel>,
+    /// Estimates of the probe phases.
+    pub liquidity: LiquidityTable,
+    pub jam: JamResults,
+    /// Whether every assertion held.
+    pub passed: bool,
+}
+
+/// Cancels the payments held by the jam phases, counting the ones still held.
+async fn release<B: LightningBackend>(jam: &mut JamResults, nodes: &mut HashMap<String, B>) {
+    for mut htlc in jam.holding.drain(..) {
+        let client = node(nodes, &htlc.receiver);
+        if strategy::lookup(client, htlc.hash, htlc.kind).await.state
+            == InvoiceState::Accepted as i32
+        {
+            jam.held += 1;
+        }
+        strategy::cancel(client, htlc.hash, htlc.kind).await;
+        htlc.state = JamState::Released;
+        events::publish(Event::Jam(htlc));
+    }
+}
+
+/// Runs every phase of `scenario` against `nodes`, then checks its assertions. `events` are
+/// handed to the jamming strategies, channels opened during setup are recorded in `channel_log`.
+/// A setup our wallets can't fund stops the scenario, which then fails.
+pub async fn run<B: LightningBackend>(
+    scenario: Scenario,
+    mut nodes: HashMap<String, B>,
+    target: String,
+    mut events: Option<UnboundedReceiver<StrategyEvent>>,
+    mut channel_log: Option<ChannelLog>,
+    traffic: TrafficStats,
+    timeline: Timeline,
+) -> ScenarioResults {
+    log!("running scenario {}", scenario.name);
+    let mut jam = JamResults::default();
+    let mut setup_channels = Vec::new();
+    let mut liquidity = LiquidityTable::new();
+    let mut snapshots = Vec::new();
+    let mut aborted = false;
+
+    if let Some(path) = &scenario.mission_control_file {
+        for (name, snapshot) in mission_control::load(path) {
+            mission_control::restore(node(&mut nodes, &name), snapshot).await;
+            log!(
+                "{}: restored mission control left by an unfinished run",
+                name
+            );
+        }
+    }
+
+    for phase in scenario.phases {
+        match phase {
+            Phase::Setup {
+                channels,
+                rebalance,
+            } => {
+                timeline.set_phase("setup");
+                let any = nodes.values_mut().next().unwrap();
+                let target_peers = any.graph_get_node_peers(target.clone()).await;
+                if let Some(channel) = channels
+                    .iter()
+                    .find(|channel| channel.peer_index >= target_peers.len())
+                {
+                    log!(
+                        "{}: the target has no peer {}, it has {}, stopping {}",
+                        channel.node,
+                        channel.peer_index,
+                        target_peers.len(),
+                        scenario.name
+                    );
+                    aborted = true;
+                    break;
+                }
+                let requests: Vec<_> = channels
+                    .iter()
+                    .map(|channel| ChannelRequest {
+                        node: channel.node.clone(),
+                        peer: target_peers[channel.peer_index].clone(),
+                        amount: channel.amount,
+                        push: channel.push,
+                        options: channel.options.clone(),
+                    })
+                    .collect();
+                let opened = match setup::open_channels(
+                    &mut nodes,
+                    &requests,
+                    rebalance,
+                    channel_log.as_mut(),
+                )
+                .await
+                {
+                    Ok(opened) => opened,
+                    Err(err) => {
+                        log!("{}, stopping {}", err, scenario.name);
+                        aborted = true;
+                        break;
+                    }
+                };
+                log!("waiting for channels to confirm...");
+                for channel in &channels {
+                    node(&mut nodes, &channel.node)
+                        .wait_channels_confirmed()
+                        .await;
+                }
+                setup::set_policies(&mut nodes, &requests, &opened).await;
+                for (name, point) in opened {
+                    let id = node(&mut nodes, &name).channel_id(point).await;
+                    log!(
+                        "{}: channel {} has id {}",
+                        name,
+                        point,
+                        id.map_or(String::from("unknown"), |id| id.to_string())
+                    );
+                    setup_channels.push(SetupChannel {
+                        node: name,
+                        point,
+                        id,
+                    });
+                }
+            }
+            Phase::BuildReputation {
+                sender,
+                receiver,
+                payments,
+                amount,
+                hold_secs,
+            } => {
+                timeline.set_phase("build_reputation");
+                for (preimage, hash) in gen_hash_table(payments) {
+                    let invoice = node(&mut nodes, &receiver)
+                        .add_hold_invoice(hash.to_vec(), amount)
+                        .await;
+                    node(&mut nodes, &sender).send_payment(invoice).await;
+                    sleep(Duration::from_secs(hold_secs)).await;
+                    let receiver = node(&mut nodes, &receiver);
+                    receiver.settle_invoice(preimage.to_vec()).await;
+                    receiver.lookup_invoice(hash.to_vec()).await;
+                }
+            }
+            Phase::Jam {
+                mode,
+                sender,
+                receiver,
+                htlcs,
+                amount,
+                ticks,
+                tick_ms,
+                payment,
+                split,
+                custom_records,
+            } => {
+                timeline.set_phase("jam");
+                // checked by `Scenario::parse`
+                let receiver = || receiver.clone().expect("jam needs a receiver");
+                let tick = Duration::from_millis(tick_ms);
+                let events = events.as_mut();
+                let payment = PaymentOptions {
+                    kind: payment,
+                    split,
+                    custom_records,
+                };
+                let in_flight = match mode {
+                    JamMode::Slots => {
+                        let mut strategy = SlotJam::new(
+                            sender,
+                            receiver(),
+                            htlcs.unwrap_or(483),
+                            amount.unwrap_or(1),
+                        );
+                        strategy::run(
+                            &mut strategy,
+                            &mut nodes,
+                            &target,
+                            tick,
+                            payment,
+                            &liquidity,
+                            events,
+                        )
+                        .await
+                    }
+                    JamMode::Liquidity => {
+                        let mut strategy = LiquidityJam::new(
+                            sender,
+                            receiver(),
+                            htlcs.unwrap_or(1),
+                            amount.expect("liquidity jam needs an amount"),
+                        );
+                        strategy::run(
+                            &mut strategy,
+                            &mut nodes,
+                            &target,
+                            tick,
+                            payment,
+                            &liquidity,
+                            events,
+                        )
+                        .await
+                    }
+                    JamMode::Circular => {
+                        let mut strategy =
+                            CircularJam::new(sender, htlcs.unwrap_or(483), amount.unwrap_or(1));
+                        strategy::run(
+                            &mut strategy,
+                            &mut nodes,
+                            &target,
+                            tick,
+                            payment,
+                            &liquidity,
+                            events,
+                        )
+                        .await
+                    }
+                    JamMode::Fast => {
+                        let mut strategy = FastJam::new(
+                            sender,
+                            receiver(),
+                            htlcs.unwrap_or(50),
+                            amount.unwrap_or(1),
+                            ticks.unwrap_or(60),
+                        );
+                        strategy::run(
+                            &mut strategy,
+                            &mut nodes,
+                            &target,
+                            tick,
+                            payment,
+                            &liquidity,
+                            events,
+                        )
+                        .await
+                    }
+                };
+                let mut shards = BTreeMap::new();
+                let mut failures = FailureTable::new();
+                for htlc in in_flight.values() {
+                    let attempts = node(&mut nodes, &htlc.sender)
+                        .payment_htlcs(htlc.hash)
+                        .await;
+                    if split.is_some() {
+                        for (chan_id, count) in mpp::shard_distribution(&target, &htlc.routes) {
+                            *shards.entry(chan_id).or_default() += count;
+                        }
+                    }
+                    // the target fails jam HTLCs as soon as they reach it, in the block they
+                    // were sent in, while the ones it forwarded are failed by the receiver when
+                    // released and are not tallied
+                    failures::tally(&mut failures, &target, &attempts, htlc.sent_height);
+                }
+                if split.is_some() {
+                    mpp::print_distribution(&shards);
+                    for (chan_id, count) in shards {
+                        *jam.shards.entry(chan_id).or_default() += count;
+                    }
+                }
+                if !failures.is_empty() {
+                    failures::print_failures(&failures);
+                }
+                for (chan_id, causes) in failures {
+                    for (cause, count) in causes {
+                        *jam.failures
+                            .entry(chan_id)
+                            .or_default()
+                            .entry(cause)
+                            .or_default() += count;
+                    }
+                }
+                jam.sent += in_flight.len() as u64;
+                jam.holding.extend(
+                    in_flight
+                        .into_values()
+                        .filter(|htlc| htlc.state.is_active()),
+                );
+            }
+            Phase::Probe {
+                node: prober,
+                max_probes,
+            } => {
+                timeline.set_phase("probe");
+                let estimates =
+                    probe::probe_target(node(&mut nodes, &prober), &target, max_probes).await;
+                probe::print_estimates(&estimates);
+                liquidity.extend(estimates);
+            }
+            Phase::MissionControl {
+                nodes: names,
+                reset,
+                seed,
+                tuning,
+            } => {
+                timeline.set_phase("mission_control");
+                for name in names {
+                    let client = node(&mut nodes, &name);
+                    if !snapshots
+                        .iter()
+                        .any(|(snapshotted, _)| *snapshotted == name)
+                    {
+                        let snapshot = mission_control::snapshot(client).await;
+                        log!(
+                            "{}: saved mission control with {} pairs",
+                            name,
+                            snapshot.pairs.len()
+                        );
+                        snapshots.push((name.clone(), snapshot));
+                        if let Some(path) = &scenario.mission_control_file {
+                            mission_control::save(path, &snapshots);
+                        }
+                    }
+                    if reset {
+                        client.reset_mission_control().await;
+                        log!("{}: reset mission control", name);
+                    }
+                    if !tuning.is_empty() {
+                        let config = tuning.apply(&client.mission_control_config().await);
+                        log!("{}: mission control config {:?}", name, config);
+                        client.set_mission_control_config(config).await;
+                    }
+                    if seed {
+                        let pairs = mission_control::seed_target(client, &target, &liquidity).await;
+                        log!("{}: seeded {} pairs through the target", name, pairs);
+                    }
+                }
+            }
+            Phase::WaitBlocks { blocks } => {
+                timeline.set_phase(if jam.holding.is_empty() {
+                    "wait_blocks"
+                } else {
+                    "jam"
+                });
+                let client = nodes.values_mut().next().unwrap();
+                let start = client.get_block_height().await;
+                log!("waiting for {} blocks from height {}", blocks, start);
+                // the dashboard may ask for what we hold back in the meantime
+                let released = tokio::select! {
+                    _ = client.wait_blocks(blocks) => false,
+                    _ = CONTROLS.wait_release_all(), if !jam.holding.is_empty() => true,
+                };
+                if released {
+                    log!("releasing held payments early");
+                    release(&mut jam, &mut nodes).await;
+                }
+            }
+            Phase::Release => {
+                timeline.set_phase("release");
+                release(&mut jam, &mut nodes).await;
+            }
+            Phase::Report => {
+                timeline.set_phase("report");
+                traffic.stop().await;
+                let total = traffic.total();
+                log!("=== {} ===", scenario.name);
+                log!("jam htlcs sent: {}, held: {}", jam.sent, jam.held);
+                if !jam.failures.is_empty() {
+                    failures::print_failures(&jam.failures);
+                }
+                log!(
+                    "honest payments: {}, failed: {} ({:.1}%)",
+                    total.sent,
+                    total.failed,
+                    total.failure_rate() * 100.0
+                );
+                let jam_phase = traffic.phase("jam");
+                log!(
+                    "honest payments during jam: {}, failed: {} ({:.1}%)",
+                    jam_phase.sent,
+                    jam_phase.failed,
+                    jam_phase.failure_rate() * 100.0
+                );
+            }
+        }
+    }
+    for (name, snapshot) in snapshots {
+        mission_control::restore(node(&mut nodes, &name), snapshot).await;
+        log!("{}: restored mission control", name);
+    }
+    if let Some(path) = &scenario.mission_control_file {
+        let _ = std::fs::remove_file(path);
+    }
+    timeline.set_phase("done");
+    traffic.stop().await;
+
+    // a scenario stopped early fails without checking its assertions
+    let mut passed = !aborted;
+    let assertions = if aborted {
+        Vec::new()
+    } else {
+        scenario.assertions
+    };
+    for assertion in assertions {
+        let stats = match &assertion.phase {
+            Some(phase) => traffic.phase(phase),
+            None => traffic.total(),
+        };
+        let value = match assertion.metric {
+            Metric::HonestPayments => stats.sent as f64,
+            Metric::HonestFailureRate => stats.failure_rate(),
+            Metric::JamHtlcsSent => jam.sent as f64,
+            Metric::JamHtlcsHeld => jam.held as f64,
+        };
+        let ok = assertion.min.is_none_or(|min| value >= min)
+            && assertion.max.is_none_or(|max| value <= max);
+        log!(
+            "[{}] {:?}{} = {} (min: {:?}, max: {:?})",
+            if ok { "PASS" } else { "FAIL" },
+            assertion.metric,
+            assertion
+                .phase
+                .map_or(String::new(), |phase| format!(" during {}", phase)),
+            value,
+            assertion.min,
+            assertion.max,
+        );
+        passed &= ok;
+    }
+    ScenarioResults {
+        channels: setup_channels,
+        liquidity,
+        jam,
+        passed,
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashMap};
+
+use fedimint_tonic_lnd::lnrpc;
+use serde::Deserialize;
+
+use crate::backend::{node, LightningBackend};
+use crate::channel_log::ChannelLog;
+use crate::channel_point::ChannelPoint;
+use crate::funding::{self, Underfunded};
+
+/// A channel one of our nodes should have with a peer of the target.
+pub struct ChannelRequest {
+    pub node: String,
+    pub peer: String,
+    /// Capacity, funded by `node`.
+    pub amount: i64,
+    pub push: i64,
+    pub options: ChannelOptions,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitmentType {
+    Legacy,
+    StaticRemoteKey,
+    Anchors,
+}
+
+impl From<CommitmentType> for lnrpc::CommitmentType {
+    fn from(commitment_type: CommitmentType) -> Self {
+        match commitment_type {
+            CommitmentType::Legacy => lnrpc::CommitmentType::Legacy,
+            CommitmentType::StaticRemoteKey => lnrpc::CommitmentType::StaticRemoteKey,
+            CommitmentType::Anchors => lnrpc::CommitmentType::Anchors,
+        }
+    }
+}
+
+/// Properties of a new channel beyond its amounts, unset ones are left to LND. The simulator
+/// ignores them.
+#[derive(Deserialize, Clone, Default)]
+pub struct ChannelOptions {
+    /// Not announced to the network.
+    #[serde(default)]
+    pub private: bool,
+    pub commitment_type: Option<CommitmentType>,
+    /// Usable before the funding transaction confirms. Needs anchors and a peer accepting it.
+    #[serde(default)]
+    pub zero_conf: bool,
+    /// Use an alias instead of the confirmed short channel id. Needs anchors.
+    #[serde(default)]
+    pub scid_alias: bool,
+    /// Fee rate of the funding transaction.
+    pub sat_per_vbyte: Option<u64>,
+    /// Smallest HTLC we accept from the peer.
+    pub min_htlc_msat: Option<i64>,
+    /// Most HTLCs the peer may have pending towards us.
+    pub remote_max_htlcs: Option<u32>,
+    /// Longest CSV delay on our funds we accept from the peer.
+    pub max_local_csv: Option<u32>,
+    /// Forwarding policy set once the channel is open, as `OpenChannel` can't. Unset ones take
+    /// LND's defaults when any of them is set.
+    pub base_fee_msat: Option<i64>,
+    pub fee_rate_ppm: Option<u32>,
+    pub time_lock_delta: Option<u32>,
+}
+
+impl ChannelOptions {
+    /// Whether `BatchOpenChannel` supports every option set.
+    pub fn batchable(&self) -> bool {
+        !self.zero_conf
+            && !self.scid_alias
+            && self.remote_max_htlcs.is_none()
+            && self.max_local_csv.is_none()
+    }
+
+    fn has_policy(&self) -> bool {
+        self.base_fee_msat.is_some()
+            || self.fee_rate_ppm.is_some()
+            || self.time_lock_delta.is_some()
+    }
+}
+
+async fn connect<B: LightningBackend>(client: &mut B, request: &ChannelRequest) {
+    assert!(
+        client.connect_peer(request.peer.clone()).await,
+        "{} could not connect to {}",
+        request.node,
+        request.peer
+    );
+}
+
+/// Opens the requested channels that don't exist yet, connecting to each peer first. An open or
+/// pending channel of the same capacity with the same peer counts as existing, so running a
+/// setup twice does not open duplicates. The remaining opens are checked against the wallets
+/// with [`funding::ensure_funded`]. A node opening several channels funds them all in one
+/// transaction with `BatchOpenChannel`, falling back to opening them one by one if that fails
+/// or their options need `OpenChannel`. New channels are recorded in `log`.
+///
+/// Returns the (node, funding outpoint) of every requested channel, reused or new, in the order
+/// of `requests`, or the nodes that can't afford their new channels.
+pub async fn open_channels<B: LightningBackend>(
+    nodes: &mut HashMap<String, B>,
+    requests: &[ChannelRequest],
+    rebalance: bool,
+    mut log: Option<&mut ChannelLog>,
+) -> Result<Vec<(String, ChannelPoint)>, Underfunded> {
+    // existing channels not yet matched to a request, by (node, peer)
+    let mut existing: HashMap<(String, String), Vec<(ChannelPoint, i64)>> = HashMap::new();
+    let mut points: Vec<Option<ChannelPoint>> = Vec::new();
+    for request in requests {
+        let key = (request.node.clone(), request.peer.clone());
+        if !existing.contains_key(&key) {
+            let found = node(nodes, &request.node)
+                .channels_with(request.peer.clone())
+                .await;
+            existing.insert(key.clone(), found);
+        }
+        let found = existing.get_mut(&key).unwrap();
+        let reused = found
+            .iter()
+            .position(|(_, capacity)| *capacity == request.amount)
+            .map(|i| found.swap_remove(i).0);
+        if let Some(point) = reused {
+            log!(
+                "{} already has a {} sat channel with {} at {}, reusing it",
+                request.node,
+                request.amount,
+                request.peer,
+                point
+            );
+        }
+        points.push(reused);
+    }
+
+    let amounts: Vec<_> = requests
+        .iter()
+        .zip(&points)
+        .filter(|(_, point)| point.is_none())
+        .map(|(request, _)| (request.node.clone(), request.amount))
+        .collect();
+    funding::ensure_funded(nodes, &amounts, rebalance).await?;
+
+    // new channels of each node go in a single funding transaction when their options allow it
+    let mut batches: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, request) in requests.iter().enumerate() {
+        if points[i].is_none() && request.options.batchable() {
+            batches.entry(&request.node).or_default().push(i);
+        }
+    }
+    for (name, indices) in batches {
+        if indices.len() < 2 {
+            continue;
+        }
+        let client = node(nodes, name);
+        for &i in &indices {
+            connect(client, &requests[i]).await;
+        }
+        let batch: Vec<&ChannelRequest> = indices.iter().map(|&i| &requests[i]).collect();
+        match client.batch_open_channels(&batch).await {
+            Ok(opened) => {
+                log!(
+                    "{}: opened {} channels in one transaction",
+                    name,
+                    opened.len()
+                );
+                for (&i, point) in indices.iter().zip(opened) {
+                    if let Some(log) = log.as_mut() {
+                        log.record(name, &point);
+                    }
+                    points[i] = Some(point);
+                }
+            }
+            Err(err) => log!(
+                "{}: batch open failed ({}), opening channels one by one",
+                name,
+                err
+            ),
+        }
+    }
+
+    for (request, point) in requests.iter().zip(points.iter_mut()) {
+        if point.is_some() {
+            continue;
+        }
+        let client = node(nodes, &request.node);
+        connect(client, request).await;
+        let opened = client
+            .open_channel(
+                request.peer.clone(),
+                request.amount,
+                request.push,
+                &request.options,
+            )
+            .await;
+        if let Some(log) = log.as_mut() {
+            log.record(&request.node, &opened);
+        }
+        *point = Some(opened);
+    }
+    Ok(requests
+        .iter()
+        .zip(points)
+        .map(|(request, point)| (request.node.clone(), point.unwrap()))
+        .collect())
+}
+
+/// Sets the forwarding policy of the requests' options on their channels, as returned by
+/// [`open_channels`]. Channels must be open.
+pub async fn set_policies<B: LightningBackend>(
+    nodes: &mut HashMap<String, B>,
+    requests: &[ChannelRequest],
+    channels: &[(String, ChannelPoint)],
+) {
+    for (request, (name, point)) in requests.iter().zip(channels) {
+        let options = &request.options;
+        if !options.has_policy() {
+            continue;
+        }
+        // LND's defaults
+        let base_fee_msat = options.base_fee_msat.unwrap_or(1000);
+        let fee_rate_ppm = options.fee_rate_ppm.unwrap_or(1);
+        let time_lock_delta = options.time_lock_delta.unwrap_or(80);
+        node(nodes, name)
+            .update_channel_policy(*point, base_fee_msat, fee_rate_ppm, time_lock_delta)
+            .await;
+        log!(
+            "{}: {} forwards at {} msat + {} ppm, cltv delta {}",
+            name,
+            point,
+            base_fee_msat,
+            fee_rate_ppm,
+            time_lock_delta
+        );
+    }
+}
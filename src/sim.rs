@@ -0,0 +1,1029 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_tonic_lnd::lnrpc::failure::FailureCode;
+use fedimint_tonic_lnd::lnrpc::htlc_attempt::HtlcStatus;
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{
+    ChannelEdge, ChannelUpdate, Failure, Hop as RouteHop, HtlcAttempt, Invoice, InvoiceHtlc,
+    InvoiceHtlcState, Route,
+};
+use fedimint_tonic_lnd::routerrpc::{MissionControlConfig, PairHistory};
+
+use crate::backend::LightningBackend;
+use crate::channel_point::ChannelPoint;
+use crate::events::{self, Event};
+use crate::invoice_feed::InvoiceEvent;
+use crate::mpp::Split;
+use crate::records::CustomRecords;
+use crate::setup::{ChannelOptions, ChannelRequest};
+use crate::spontaneous;
+
+/// HTLC slots per channel direction, LND's default `max_accepted_htlcs`.
+const MAX_HTLCS: usize = 483;
+/// CLTV delta of every simulated channel, LND's default `time_lock_delta`.
+const CLTV_DELTA: u32 = 80;
+const FINAL_CLTV_DELTA: u32 = 80;
+/// Routes needing a longer total timelock are rejected, as LND does.
+const MAX_CLTV_EXPIRY: u32 = 2016;
+/// Held HTLCs are cancelled back this many blocks before they expire at the receiver.
+const CANCEL_BEFORE_EXPIRY: u32 = 12;
+const MAX_ATTEMPTS: usize = 10;
+
+/// Deterministic pubkey of a simulated node.
+pub fn pubkey(alias: &str) -> String {
+    let mut key = vec![2u8];
+    key.extend_from_slice(&sha256::Hash::hash(alias.as_bytes()).to_byte_array());
+    hex::encode(key)
+}
+
+struct Htlc {
+    hash: Vec<u8>,
+    from_node1: bool,
+    amount_msat: u64,
+    expiry: u32,
+    endorsed: bool,
+}
+
+struct Channel {
+    id: u64,
+    node1: String,
+    node2: String,
+    capacity_msat: u64,
+    /// Settled balance of `node1`; in-flight htlcs are not deducted.
+    balance1_msat: u64,
+    htlcs: Vec<Htlc>,
+}
+
+impl Channel {
+    fn other(&self, node: &str) -> &str {
+        if self.node1 == node {
+            &self.node2
+        } else {
+            &self.node1
+        }
+    }
+
+    /// Whether an htlc of `amount_msat` fits in the slots and liquidity of one direction.
+    fn can_add(&self, from_node1: bool, amount_msat: u64) -> bool {
+        let balance = if from_node1 {
+            self.balance1_msat
+        } else {
+            self.capacity_msat - self.balance1_msat
+        };
+        let (slots, locked) = self
+            .htlcs
+            .iter()
+            .filter(|htlc| htlc.from_node1 == from_node1)
+            .fold((0, 0), |(slots, locked), htlc| {
+                (slots + 1, locked + htlc.amount_msat)
+            });
+        slots < MAX_HTLCS && locked + amount_msat <= balance
+    }
+
+    fn resolve(&mut self, hash: &[u8], settle: bool) {
+        let i = self
+            .htlcs
+            .iter()
+            .position(|htlc| htlc.hash == hash)
+            .unwrap();
+        let htlc = self.htlcs.remove(i);
+        if settle {
+            if htlc.from_node1 {
+                self.balance1_msat -= htlc.amount_msat;
+            } else {
+                self.balance1_msat += htlc.amount_msat;
+            }
+        }
+    }
+}
+
+/// Channel index and direction of one hop of a route.
+type Hop = (usize, bool);
+
+struct SimInvoice {
+    invoice: Invoice,
+    receiver: String,
+    /// Route of every htlc paying it.
+    shards: Vec<Vec<Hop>>,
+    /// Route of every htlc that could not be locked, and the position in it of the channel that
+    /// refused it.
+    failed: Vec<(Vec<Hop>, usize)>,
+}
+
+/// Mission control state of a node. It is only stored: simulated routing ignores it.
+struct MissionControl {
+    pairs: Vec<PairHistory>,
+    config: MissionControlConfig,
+}
+
+impl Default for MissionControl {
+    /// LND's defaults.
+    fn default() -> Self {
+        MissionControl {
+            pairs: Vec::new(),
+            config: MissionControlConfig {
+                half_life_seconds: 3600,
+                hop_probability: 0.6,
+                weight: 0.5,
+                maximum_payment_results: 1000,
+                minimum_failure_relax_interval: 60,
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct Network {
+    height: u32,
+    channels: Vec<Channel>,
+    invoices: HashMap<Vec<u8>, SimInvoice>,
+    /// On-chain balance of each node, in sats. On-chain fees are not simulated.
+    wallets: HashMap<String, i64>,
+    mission_control: HashMap<String, MissionControl>,
+}
+
+impl Network {
+    /// Shortest route by hop count avoiding `excluded` channels. Ties are broken by channel
+    /// creation order, so the result is deterministic. A route from a node to itself leaves
+    /// through one of its channels and comes back through another.
+    fn find_route(&self, from: &str, to: &str, excluded: &HashSet<usize>) -> Option<Vec<Hop>> {
+        if from != to {
+            return self.shortest_route(from, to, excluded);
+        }
+        self.channels
+            .iter()
+            .enumerate()
+            .filter(|(i, channel)| {
+                !excluded.contains(i) && (channel.node1 == from || channel.node2 == from)
+            })
+            .filter_map(|(i, channel)| {
+                let mut excluded = excluded.clone();
+                excluded.insert(i);
+                let back = self.shortest_route(channel.other(from), to, &excluded)?;
+                let mut route = vec![(i, channel.node1 == from)];
+                route.extend(back);
+                Some(route)
+            })
+            .min_by_key(|route| route.len())
+    }
+
+    fn shortest_route(&self, from: &str, to: &str, excluded: &HashSet<usize>) -> Option<Vec<Hop>> {
+        let mut previous: HashMap<&str, Hop> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut route = Vec::new();
+                let mut node = to;
+                while node != from {
+                    let hop = previous[node];
+                    route.push(hop);
+                    node = self.channels[hop.0].other(node);
+                }
+                route.reverse();
+                return Some(route);
+            }
+            for (i, channel) in self.channels.iter().enumerate() {
+                if excluded.contains(&i) || (channel.node1 != node && channel.node2 != node) {
+                    continue;
+                }
+                let next = channel.other(node);
+                if next != from && !previous.contains_key(next) {
+                    previous.insert(next, (i, channel.node1 == node));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Locks htlcs along a route to the invoice's receiver, retrying around channels without
+    /// free slots or liquidity like LND's mission control would. With a `split`, the invoice is
+    /// paid in equal shards, each preferring the least used of parallel channels. The receiver
+    /// sees `custom_records` on every htlc.
+    fn pay(
+        &mut self,
+        sender: &str,
+        hash: &[u8],
+        split: Option<Split>,
+        custom_records: &CustomRecords,
+    ) -> Result<(), &'static str> {
+        let invoice = self.invoices.get(hash).ok_or("unknown invoice")?;
+        if invoice.invoice.state != InvoiceState::Open as i32 {
+            return Err("invoice not open");
+        }
+        let receiver = invoice.receiver.clone();
+        let amount_msat = invoice.invoice.value_msat as u64;
+        let parts = match split {
+            Some(split) if split.max_shard_size_msat > 0 => {
+                amount_msat.div_ceil(split.max_shard_size_msat).max(1)
+            }
+            Some(split) => split.max_parts.max(1) as u64,
+            None => 1,
+        };
+        if split.is_some_and(|split| parts > split.max_parts.max(1) as u64) {
+            return Err("too many shards needed");
+        }
+
+        let mut uses = HashMap::new();
+        let mut shards: Vec<Vec<Hop>> = Vec::new();
+        for part in 0..parts {
+            let shard_msat = amount_msat / parts + u64::from(part < amount_msat % parts);
+            match self.lock_shard(sender, &receiver, hash, shard_msat, &uses) {
+                Ok(route) => {
+                    for (i, _) in &route {
+                        *uses.entry(*i).or_insert(0) += 1;
+                    }
+                    shards.push(route);
+                }
+                Err(reason) => {
+                    for (i, _) in shards.into_iter().flatten() {
+                        self.channels[i].resolve(hash, false);
+                    }
+                    self.invoices.get_mut(hash).unwrap().invoice.htlcs.clear();
+                    return Err(reason);
+                }
+            }
+        }
+        let invoice = self.invoices.get_mut(hash).unwrap();
+        invoice.invoice.state = InvoiceState::Accepted as i32;
+        invoice.invoice.amt_paid_msat = amount_msat as i64;
+        for htlc in invoice.invoice.htlcs.iter_mut() {
+            htlc.custom_records = custom_records.clone();
+        }
+        invoice.shards = shards;
+        events::publish(Event::Invoice(InvoiceEvent::Accepted {
+            node: receiver,
+            hash: hash.to_vec(),
+            amount_msat: amount_msat as i64,
+        }));
+        Ok(())
+    }
+
+    /// Locks one shard of `amount_msat` along a route, avoiding channels used more than a
+    /// parallel one according to `uses`, and adds its htlc to the invoice. Returns the route.
+    fn lock_shard(
+        &mut self,
+        sender: &str,
+        receiver: &str,
+        hash: &[u8],
+        amount_msat: u64,
+        uses: &HashMap<usize, usize>,
+    ) -> Result<Vec<Hop>, &'static str> {
+        let used = |i: usize| uses.get(&i).copied().unwrap_or(0);
+        let mut excluded = HashSet::new();
+        for _ in 0..MAX_ATTEMPTS {
+            let busier: HashSet<usize> = (0..self.channels.len())
+                .filter(|i| !excluded.contains(i))
+                .filter(|&i| {
+                    let channel = &self.channels[i];
+                    self.channels.iter().enumerate().any(|(j, other)| {
+                        !excluded.contains(&j)
+                            && used(j) < used(i)
+                            && ((other.node1 == channel.node1 && other.node2 == channel.node2)
+                                || (other.node1 == channel.node2 && other.node2 == channel.node1))
+                    })
+                })
+                .collect();
+            let route = self
+                .find_route(
+                    sender,
+                    receiver,
+                    &excluded.union(&busier).copied().collect(),
+                )
+                .or_else(|| self.find_route(sender, receiver, &excluded))
+                .ok_or("no route")?;
+            let total_cltv = FINAL_CLTV_DELTA + CLTV_DELTA * (route.len() as u32 - 1);
+            if total_cltv > MAX_CLTV_EXPIRY {
+                return Err("cltv limit exceeded");
+            }
+            if let Some(position) = route
+                .iter()
+                .position(|(i, from_node1)| !self.channels[*i].can_add(*from_node1, amount_msat))
+            {
+                excluded.insert(route[position].0);
+                let invoice = self.invoices.get_mut(hash).unwrap();
+                invoice.failed.push((route, position));
+                continue;
+            }
+
+            let mut expiry = self.height + total_cltv;
+            for (i, from_node1) in &route {
+                self.channels[*i].htlcs.push(Htlc {
+                    hash: hash.to_vec(),
+                    from_node1: *from_node1,
+                    amount_msat,
+                    expiry,
+                    endorsed: true,
+                });
+                expiry -= CLTV_DELTA;
+            }
+            let (last, _) = *route.last().unwrap();
+            let htlc = self.channels[last].htlcs.last().unwrap();
+            let htlc = InvoiceHtlc {
+                chan_id: self.channels[last].id,
+                amt_msat: amount_msat,
+                accept_height: self.height as i32,
+                expiry_height: htlc.expiry as i32,
+                state: InvoiceHtlcState::Accepted as i32,
+                incoming_endorsed: htlc.endorsed,
+                ..Default::default()
+            };
+            self.invoices
+                .get_mut(hash)
+                .unwrap()
+                .invoice
+                .htlcs
+                .push(htlc);
+            return Ok(route);
+        }
+        Err("too many attempts")
+    }
+
+    /// Where a payment of `amount_msat` along `hops` would fail, without locking anything.
+    /// Nodes forward over any of their channels with the next hop that can take it, and the
+    /// destination rejects the unknown payment hash.
+    fn probe(&self, sender: &str, hops: &[String], amount_msat: u64) -> Failure {
+        let mut from = sender;
+        for (index, to) in hops.iter().enumerate() {
+            let mut channels = self
+                .channels
+                .iter()
+                .filter(|channel| {
+                    (channel.node1 == from && channel.node2 == *to)
+                        || (channel.node2 == from && channel.node1 == *to)
+                })
+                .peekable();
+            let code = if channels.peek().is_none() {
+                Some(FailureCode::UnknownNextPeer)
+            } else if !channels.any(|channel| channel.can_add(channel.node1 == from, amount_msat)) {
+                Some(FailureCode::TemporaryChannelFailure)
+            } else {
+                None
+            };
+            if let Some(code) = code {
+                return Failure {
+                    code: code as i32,
+                    failure_source_index: index as u32,
+                    ..Default::default()
+                };
+            }
+            from = to;
+        }
+        Failure {
+            code: FailureCode::IncorrectOrUnknownPaymentDetails as i32,
+            failure_source_index: hops.len() as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Settles or cancels an invoice, returning whether anything changed. Only accepted invoices
+    /// can be settled.
+    fn resolve(&mut self, hash: &[u8], settle: bool) -> bool {
+        let invoice = self.invoices.get_mut(hash).expect("invoice not found");
+        let state = invoice.invoice.state;
+        if state == InvoiceState::Settled as i32 || state == InvoiceState::Canceled as i32 {
+            return false;
+        }
+        if state == InvoiceState::Accepted as i32 {
+            for (i, _) in invoice.shards.drain(..).flatten() {
+                self.channels[i].resolve(hash, settle);
+            }
+            let htlc_state = if settle {
+                InvoiceHtlcState::Settled
+            } else {
+                InvoiceHtlcState::Canceled
+            };
+            for htlc in invoice.invoice.htlcs.iter_mut() {
+                htlc.state = htlc_state as i32;
+            }
+        } else if settle {
+            return false;
+        }
+        invoice.invoice.state = if settle {
+            InvoiceState::Settled as i32
+        } else {
+            InvoiceState::Canceled as i32
+        };
+        let (node, hash) = (invoice.receiver.clone(), hash.to_vec());
+        events::publish(Event::Invoice(if settle {
+            InvoiceEvent::Settled {
+                node,
+                hash,
+                amount_msat: invoice.invoice.amt_paid_msat,
+            }
+        } else {
+            InvoiceEvent::Cancelled { node, hash }
+        }));
+        true
+    }
+
+    fn mine(&mut self, blocks: u32) {
+        self.height += blocks;
+        let expiring: Vec<Vec<u8>> =
+            self.invoices
+                .iter()
+                .filter(|(_, invoice)| {
+                    invoice.invoice.state == InvoiceState::Accepted as i32
+                        && invoice.invoice.htlcs.iter().any(|htlc| {
+                            htlc.expiry_height as u32 <= self.height + CANCEL_BEFORE_EXPIRY
+                        })
+                })
+                .map(|(hash, _)| hash.clone())
+                .collect();
+        for hash in expiring {
+            log!("sim: cancelling expiring htlcs of {}", hex::encode(&hash));
+            self.resolve(&hash, false);
+        }
+    }
+}
+
+/// In-process Lightning network modelling channels, HTLC slots, liquidity, CLTV and
+/// endorsement, so strategies can be exercised without an LND cluster. Fees are not modelled.
+/// Invoices publish the events an [`crate::invoice_feed`] would on the event bus.
+#[derive(Clone, Default)]
+pub struct Simulator(Arc<Mutex<Network>>);
+
+impl Simulator {
+    pub fn new() -> Self {
+        Simulator::default()
+    }
+
+    /// Opens a confirmed channel between two nodes and returns its id.
+    pub fn add_channel(&self, node1: &str, node2: &str, capacity: i64, push: i64) -> u64 {
+        let mut network = self.0.lock().unwrap();
+        let id = network.channels.len() as u64 + 1;
+        network.channels.push(Channel {
+            id,
+            node1: String::from(node1),
+            node2: String::from(node2),
+            capacity_msat: capacity as u64 * 1000,
+            balance1_msat: (capacity - push) as u64 * 1000,
+            htlcs: Vec::new(),
+        });
+        id
+    }
+
+    /// Credits `amount` sats to the on-chain wallet of a node.
+    pub fn fund(&self, pubkey: &str, amount: i64) {
+        *self
+            .0
+            .lock()
+            .unwrap()
+            .wallets
+            .entry(String::from(pubkey))
+            .or_default() += amount;
+    }
+
+    pub fn node(&self, pubkey: &str) -> SimNode {
+        SimNode {
+            network: self.0.clone(),
+            pubkey: String::from(pubkey),
+        }
+    }
+}
+
+/// Made-up funding outpoint of a simulated channel.
+fn funding_point(id: u64) -> ChannelPoint {
+    ChannelPoint {
+        txid: sha256::Hash::hash(&id.to_be_bytes()).to_byte_array(),
+        output_index: 0,
+    }
+}
+
+/// Handle acting as one node of a [`Simulator`].
+#[derive(Clone)]
+pub struct SimNode {
+    network: Arc<Mutex<Network>>,
+    pubkey: String,
+}
+
+impl LightningBackend for SimNode {
+    async fn get_pubkey(&mut self) -> String {
+        self.pubkey.clone()
+    }
+
+    async fn graph_get_node_channels(&mut self, node_pubkey: String) -> Vec<ChannelEdge> {
+        let network = self.network.lock().unwrap();
+        network
+            .channels
+            .iter()
+            .filter(|channel| channel.node1 == node_pubkey || channel.node2 == node_pubkey)
+            .map(|channel| ChannelEdge {
+                channel_id: channel.id,
+                chan_point: funding_point(channel.id).to_string(),
+                node1_pub: channel.node1.clone(),
+                node2_pub: channel.node2.clone(),
+                capacity: (channel.capacity_msat / 1000) as i64,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn graph_get_node_peers(&mut self, node_pubkey: String) -> Vec<String> {
+        let network = self.network.lock().unwrap();
+        network
+            .channels
+            .iter()
+            .filter(|channel| channel.node1 == node_pubkey || channel.node2 == node_pubkey)
+            .map(|channel| String::from(channel.other(&node_pubkey)))
+            .collect()
+    }
+
+    async fn connect_peer(&mut self, _node_pubkey: String) -> bool {
+        true
+    }
+
+    async fn channels_with(&mut self, node_pubkey: String) -> Vec<(ChannelPoint, i64)> {
+        let network = self.network.lock().unwrap();
+        network
+            .channels
+            .iter()
+            .filter(|channel| {
+                (channel.node1 == self.pubkey && channel.node2 == node_pubkey)
+                    || (channel.node2 == self.pubkey && channel.node1 == node_pubkey)
+            })
+            .map(|channel| {
+                (
+                    funding_point(channel.id),
+                    (channel.capacity_msat / 1000) as i64,
+                )
+            })
+            .collect()
+    }
+
+    async fn channel_id(&mut self, point: ChannelPoint) -> Option<u64> {
+        let network = self.network.lock().unwrap();
+        network
+            .channels
+            .iter()
+            .map(|channel| channel.id)
+            .find(|id| funding_point(*id) == point)
+    }
+
+    async fn open_channel(
+        &mut self,
+        node_pubkey: String,
+        local_funding_amount: i64,
+        push_sat: i64,
+        _options: &ChannelOptions,
+    ) -> ChannelPoint {
+        let mut network = self.network.lock().unwrap();
+        let wallet = network.wallets.entry(self.pubkey.clone()).or_default();
+        assert!(
+            *wallet >= local_funding_amount,
+            "not enough funds to open a channel"
+        );
+        *wallet -= local_funding_amount;
+        drop(network);
+        let id = Simulator(self.network.clone()).add_channel(
+            &self.pubkey,
+            &node_pubkey,
+            local_funding_amount,
+            push_sat,
+        );
+        funding_point(id)
+    }
+
+    async fn batch_open_channels(
+        &mut self,
+        channels: &[&ChannelRequest],
+    ) -> Result<Vec<ChannelPoint>, String> {
+        let mut points = Vec::new();
+        for channel in channels {
+            points.push(
+                self.open_channel(
+                    channel.peer.clone(),
+                    channel.amount,
+                    channel.push,
+                    &channel.options,
+                )
+                .await,
+            );
+        }
+        Ok(points)
+    }
+
+    async fn update_channel_policy(
+        &mut self,
+        _point: ChannelPoint,
+        _base_fee_msat: i64,
+        _fee_rate_ppm: u32,
+        _time_lock_delta: u32,
+    ) {
+    }
+
+    async fn wait_channels_confirmed(&mut self) {}
+
+    async fn add_hold_invoice(&mut self, hash: Vec<u8>, value: i64) -> String {
+        let payment_request = format!("lnsim:{}:{}", self.pubkey, hex::encode(&hash));
+        let invoice = Invoice {
+            r_hash: hash.clone(),
+            value,
+            value_msat: value * 1000,
+            payment_request: payment_request.clone(),
+            cltv_expiry: FINAL_CLTV_DELTA as u64,
+            ..Default::default()
+        };
+        self.network.lock().unwrap().invoices.insert(
+            hash,
+            SimInvoice {
+                invoice,
+                receiver: self.pubkey.clone(),
+                shards: Vec::new(),
+                failed: Vec::new(),
+            },
+        );
+        payment_request
+    }
+
+    async fn send_payment(&mut self, payment_request: String) {
+        self.send_payment_with(payment_request, None, CustomRecords::new())
+            .await
+    }
+
+    async fn send_payment_with(
+        &mut self,
+        payment_request: String,
+        split: Option<Split>,
+        custom_records: CustomRecords,
+    ) {
+        let hash = hex::decode(payment_request.rsplit(':').next().unwrap()).unwrap();
+        if let Err(reason) =
+            self.network
+                .lock()
+                .unwrap()
+                .pay(&self.pubkey, &hash, split, &custom_records)
+        {
+            log!("payment failed! ({})", reason);
+        }
+    }
+
+    /// Locked shards are in flight, shards refused by a channel failed there with
+    /// TEMPORARY_CHANNEL_FAILURE and the channel's update, as LND links fail them.
+    async fn payment_htlcs(&mut self, payment_hash: [u8; 32]) -> Vec<HtlcAttempt> {
+        let network = self.network.lock().unwrap();
+        let Some(invoice) = network.invoices.get(&payment_hash[..]) else {
+            return Vec::new();
+        };
+        let route = |shard: &[Hop]| {
+            let mut node = self.pubkey.as_str();
+            let hops = shard
+                .iter()
+                .map(|(i, _)| {
+                    let channel = &network.channels[*i];
+                    node = channel.other(node);
+                    RouteHop {
+                        chan_id: channel.id,
+                        pub_key: String::from(node),
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            Some(Route {
+                hops,
+                ..Default::default()
+            })
+        };
+        let failed = invoice.failed.iter().map(|(shard, position)| HtlcAttempt {
+            status: HtlcStatus::Failed as i32,
+            route: route(shard),
+            failure: Some(Failure {
+                code: FailureCode::TemporaryChannelFailure as i32,
+                channel_update: Some(ChannelUpdate {
+                    chan_id: network.channels[shard[*position].0].id,
+                    ..Default::default()
+                }),
+                failure_source_index: *position as u32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        invoice
+            .shards
+            .iter()
+            .map(|shard| HtlcAttempt {
+                route: route(shard),
+                ..Default::default()
+            })
+            .chain(failed)
+            .collect()
+    }
+
+    async fn probe_route(
+        &mut self,
+        hops: Vec<String>,
+        amount_msat: i64,
+    ) -> Result<Failure, String> {
+        let network = self.network.lock().unwrap();
+        Ok(network.probe(&self.pubkey, &hops, amount_msat as u64))
+    }
+
+    /// Simulated nodes hold spontaneous payments they expect like hold invoice payments.
+    async fn expect_spontaneous(&mut self, hash: [u8; 32], _preimage: [u8; 32]) -> u64 {
+        let invoice = Invoice {
+            r_hash: hash.to_vec(),
+            ..Default::default()
+        };
+        self.network.lock().unwrap().invoices.insert(
+            hash.to_vec(),
+            SimInvoice {
+                invoice,
+                receiver: self.pubkey.clone(),
+                shards: Vec::new(),
+                failed: Vec::new(),
+            },
+        );
+        spontaneous::hold_chan_id(&hash)
+    }
+
+    async fn send_spontaneous(
+        &mut self,
+        _receiver: String,
+        _chan_id: u64,
+        amount: i64,
+        preimage: [u8; 32],
+        _amp: bool,
+        _custom_records: CustomRecords,
+    ) {
+        let hash = sha256::Hash::hash(&preimage).to_byte_array();
+        let mut network = self.network.lock().unwrap();
+        let Some(expected) = network.invoices.get_mut(&hash[..]) else {
+            log!("payment failed! (not expected)");
+            return;
+        };
+        expected.invoice.value = amount;
+        expected.invoice.value_msat = amount * 1000;
+        // held before the destination, the receiver never sees the records
+        if let Err(reason) = network.pay(&self.pubkey, &hash, None, &CustomRecords::new()) {
+            log!("payment failed! ({})", reason);
+        }
+    }
+
+    async fn get_spontaneous(&mut self, hash: [u8; 32]) -> Invoice {
+        self.get_invoice(hash.to_vec()).await
+    }
+
+    async fn resolve_spontaneous(&mut self, hash: [u8; 32], settle: bool) {
+        self.network.lock().unwrap().resolve(&hash, settle);
+    }
+
+    async fn settle_invoice(&mut self, preimage: Vec<u8>) {
+        let hash = sha256::Hash::hash(&preimage).to_byte_array();
+        if self.network.lock().unwrap().resolve(&hash, true) {
+            log!("payment success!");
+        }
+    }
+
+    async fn cancel_invoice(&mut self, payment_hash: Vec<u8>) {
+        self.network.lock().unwrap().resolve(&payment_hash, false);
+    }
+
+    async fn get_invoice(&mut self, r_hash: Vec<u8>) -> Invoice {
+        let network = self.network.lock().unwrap();
+        network
+            .invoices
+            .get(&r_hash)
+            .expect("invoice not found")
+            .invoice
+            .clone()
+    }
+
+    async fn query_mission_control(&mut self) -> Vec<PairHistory> {
+        let network = self.network.lock().unwrap();
+        network
+            .mission_control
+            .get(&self.pubkey)
+            .map(|mission_control| mission_control.pairs.clone())
+            .unwrap_or_default()
+    }
+
+    async fn reset_mission_control(&mut self) {
+        let mut network = self.network.lock().unwrap();
+        if let Some(mission_control) = network.mission_control.get_mut(&self.pubkey) {
+            mission_control.pairs.clear();
+        }
+    }
+
+    async fn import_mission_control(&mut self, pairs: Vec<PairHistory>, _force: bool) {
+        let mut network = self.network.lock().unwrap();
+        let mission_control = network
+            .mission_control
+            .entry(self.pubkey.clone())
+            .or_default();
+        for pair in pairs {
+            mission_control
+                .pairs
+                .retain(|known| known.node_from != pair.node_from || known.node_to != pair.node_to);
+            mission_control.pairs.push(pair);
+        }
+    }
+
+    async fn mission_control_config(&mut self) -> MissionControlConfig {
+        let mut network = self.network.lock().unwrap();
+        network
+            .mission_control
+            .entry(self.pubkey.clone())
+            .or_default()
+            .config
+            .clone()
+    }
+
+    async fn set_mission_control_config(&mut self, config: MissionControlConfig) {
+        let mut network = self.network.lock().unwrap();
+        network
+            .mission_control
+            .entry(self.pubkey.clone())
+            .or_default()
+            .config = config;
+    }
+
+    async fn get_block_height(&mut self) -> u32 {
+        self.network.lock().unwrap().height
+    }
+
+    async fn wait_blocks(&mut self, blocks: u32) {
+        self.network.lock().unwrap().mine(blocks);
+    }
+
+    async fn wallet_balance(&mut self) -> i64 {
+        let network = self.network.lock().unwrap();
+        network
+            .wallets
+            .get(&self.pubkey)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    async fn list_unspent(&mut self) -> Vec<i64> {
+        let balance = self.wallet_balance().await;
+        if balance > 0 {
+            vec![balance]
+        } else {
+            Vec::new()
+        }
+    }
+
+    async fn estimate_fee_rate(&mut self, _conf_target: i32) -> u64 {
+        1
+    }
+
+    async fn new_address(&mut self) -> String {
+        format!("simaddr:{}", self.pubkey)
+    }
+
+    async fn send_many(&mut self, outputs: HashMap<String, i64>, _sat_per_vbyte: u64) {
+        let mut network = self.network.lock().unwrap();
+        for (addr, amount) in outputs {
+            let pubkey = addr
+                .strip_prefix("simaddr:")
+                .expect("not a simulated address");
+            *network.wallets.entry(self.pubkey.clone()).or_default() -= amount;
+            *network.wallets.entry(String::from(pubkey)).or_default() += amount;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::time::Duration;
+
+    use super::{pubkey, SimNode, Simulator};
+    use crate::mpp::{self, Split};
+    use crate::probe::{self, LiquidityTable};
+    use crate::strategy::{
+        self, FastJam, InFlightTable, JamState, JammingStrategy, LiquidityJam, PaymentOptions,
+        SlotJam,
+    };
+
+    const TICK: Duration = Duration::from_millis(1);
+
+    /// alice -> peer1 -> target -> peer2 -> bob, as `jammy simulate` sets up for the sample
+    /// scenarios. bob can receive 250k sats.
+    fn network() -> (String, HashMap<String, SimNode>) {
+        let sim = Simulator::new();
+        let target = pubkey("target");
+        for i in 0..3 {
+            sim.add_channel(&target, &pubkey(&format!("peer{}", i)), 1_000_000, 500_000);
+        }
+        sim.add_channel(&pubkey("alice"), &pubkey("peer1"), 500_000, 0);
+        sim.add_channel(&pubkey("bob"), &pubkey("peer2"), 500_000, 250_000);
+        let nodes = ["alice", "bob"]
+            .into_iter()
+            .map(|name| (String::from(name), sim.node(&pubkey(name))))
+            .collect();
+        (target, nodes)
+    }
+
+    async fn run<S: JammingStrategy>(strategy: &mut S) -> InFlightTable {
+        run_with(strategy, PaymentOptions::default()).await
+    }
+
+    async fn run_with<S: JammingStrategy>(
+        strategy: &mut S,
+        payment: PaymentOptions,
+    ) -> InFlightTable {
+        let (target, mut nodes) = network();
+        strategy::run(
+            strategy,
+            &mut nodes,
+            &target,
+            TICK,
+            payment,
+            &LiquidityTable::new(),
+            None,
+        )
+        .await
+    }
+
+    fn count(in_flight: &InFlightTable, state: JamState) -> usize {
+        in_flight
+            .values()
+            .filter(|htlc| htlc.state == state)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn slot_jam_holds_every_slot() {
+        let mut strategy = SlotJam::new(String::from("alice"), String::from("bob"), 483, 1);
+        let in_flight = run(&mut strategy).await;
+        assert_eq!(in_flight.len(), 483);
+        assert_eq!(count(&in_flight, JamState::Held), 483);
+    }
+
+    #[tokio::test]
+    async fn slot_jam_gives_up_on_htlcs_beyond_the_slots() {
+        let mut strategy = SlotJam::new(String::from("alice"), String::from("bob"), 490, 1);
+        let in_flight = run(&mut strategy).await;
+        assert_eq!(count(&in_flight, JamState::Held), 483);
+        assert_eq!(count(&in_flight, JamState::Pending), 0);
+        assert!(count(&in_flight, JamState::Failed) >= 7);
+    }
+
+    #[tokio::test]
+    async fn liquidity_jam_holds_what_the_receiver_can_take() {
+        let mut strategy =
+            LiquidityJam::new(String::from("alice"), String::from("bob"), 3, 100_000);
+        let in_flight = run(&mut strategy).await;
+        assert_eq!(count(&in_flight, JamState::Held), 2);
+        assert!(count(&in_flight, JamState::Failed) >= 1);
+    }
+
+    #[tokio::test]
+    async fn fast_jam_releases_everything_it_sends() {
+        let mut strategy = FastJam::new(String::from("alice"), String::from("bob"), 5, 1, 3);
+        let in_flight = run(&mut strategy).await;
+        assert_eq!(in_flight.len(), 15);
+        assert_eq!(count(&in_flight, JamState::Released), 15);
+    }
+
+    #[tokio::test]
+    async fn released_shards_keep_their_routes() {
+        let mut strategy = FastJam::new(String::from("alice"), String::from("bob"), 2, 2, 1);
+        let payment = PaymentOptions {
+            split: Some(Split {
+                max_parts: 2,
+                max_shard_size_msat: 0,
+            }),
+            ..Default::default()
+        };
+        let in_flight = run_with(&mut strategy, payment).await;
+        assert_eq!(count(&in_flight, JamState::Released), 2);
+        let routes: Vec<_> = in_flight
+            .values()
+            .flat_map(|htlc| htlc.routes.clone())
+            .collect();
+        assert_eq!(routes.len(), 4);
+        let shards = mpp::shard_distribution(&pubkey("target"), &routes);
+        assert_eq!(shards.values().sum::<usize>(), 8);
+    }
+
+    #[tokio::test]
+    async fn probes_converge_to_the_channel_balances() {
+        let sim = Simulator::new();
+        let target = pubkey("target");
+        let peer = pubkey("peer0");
+        let chan_id = sim.add_channel(&target, &peer, 1_000_000, 300_000);
+        // alice can reach both sides of the probed channel with room to spare
+        sim.add_channel(&pubkey("alice"), &target, 2_000_000, 0);
+        sim.add_channel(&pubkey("alice"), &peer, 2_000_000, 0);
+        let mut alice = sim.node(&pubkey("alice"));
+        let table = probe::probe_target(&mut alice, &target, 16).await;
+        assert_eq!(table.len(), 2);
+        for (from, balance) in [(&target, 700_000), (&peer, 300_000)] {
+            let estimate = &table[&(chan_id, from.clone())];
+            assert!(
+                estimate.min_sat <= balance && balance <= estimate.max_sat,
+                "{}..{} misses {}",
+                estimate.min_sat,
+                estimate.max_sat,
+                balance
+            );
+            assert!(estimate.max_sat - estimate.min_sat <= 1_000);
+            assert!(estimate.probes <= 16);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Jamming payments without invoices.
+//!
+//! A keysend or AMP payment needs no invoice from its receiver, but LND settles those it
+//! receives right away. So jammy sends them to [`HOLD_DESTINATION`], a node that does not exist,
+//! through a made-up channel of the receiving attacker node given as route hint. The receiver's
+//! HTLC interceptor is handed HTLCs for channels it doesn't know, and holds those of the
+//! payments it expects until they are settled or failed back.
+//!
+//! The keysend preimage record and the AMP fields are in the onion payload of the final hop,
+//! which no node decrypts since [`HOLD_DESTINATION`] does not exist. The receiver only sees an
+//! HTLC to forward, so it never acts as a receiver of a payment without invoice: to the target
+//! and the receiver these are plain forwards, and only our sender pays without an invoice.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_tonic_lnd::lnrpc::failure::FailureCode;
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{Invoice, InvoiceHtlc, InvoiceHtlcState};
+use fedimint_tonic_lnd::routerrpc::{
+    CircuitKey, ForwardHtlcInterceptResponse, ResolveHoldForwardAction,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::Client;
+
+/// Custom record carrying the preimage of a keysend payment.
+pub const KEYSEND_RECORD: u64 = 5482373484;
+
+/// Final node of spontaneous jamming payments: the secp256k1 generator point, a valid key that
+/// is no node's.
+pub const HOLD_DESTINATION: &str =
+    "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+/// How jamming payments are paid.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentKind {
+    /// Hold invoices created by the receiver.
+    #[default]
+    Invoice,
+    /// Keysend payments with our preimage in their custom records. The receiver holds them as
+    /// forwards without reading the record, see the [module documentation](self).
+    Keysend,
+    /// AMP payments, whose HTLC hashes are derived by the sender. They can only be failed back,
+    /// and are held as forwards like keysend payments.
+    Amp,
+}
+
+/// Made-up channel id a payment is routed over to the receiver's interceptor.
+pub fn hold_chan_id(hash: &[u8; 32]) -> u64 {
+    u64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+/// A payment the receiver expects, and the HTLCs of it being held.
+struct Expected {
+    chan_id: u64,
+    preimage: [u8; 32],
+    invoice: Invoice,
+    /// Circuit and payment hash of every held HTLC.
+    htlcs: Vec<(CircuitKey, Vec<u8>)>,
+}
+
+/// Spontaneous payments held by one node's HTLC interceptor, by the payment hash jammy chose.
+#[derive(Clone)]
+pub struct Holds {
+    responses: mpsc::Sender<ForwardHtlcInterceptResponse>,
+    expected: Arc<Mutex<HashMap<[u8; 32], Expected>>>,
+}
+
+impl Holds {
+    /// Registers as the HTLC interceptor of `client`. HTLCs that are not for an expected payment
+    /// are resumed.
+    pub async fn start(client: &mut Client) -> Self {
+        let (responses, mut htlcs) = client.htlc_interceptor().await;
+        let holds = Holds {
+            responses: responses.clone(),
+            expected: Arc::new(Mutex::new(HashMap::new())),
+        };
+        let expected = holds.expected.clone();
+        tokio::task::spawn(async move {
+            while let Some(htlc) = htlcs.message().await.unwrap() {
+                let key = htlc.incoming_circuit_key.clone();
+                let held = {
+                    let mut expected = expected.lock().unwrap();
+                    match expected
+                        .values_mut()
+                        .find(|payment| payment.chan_id == htlc.outgoing_requested_chan_id)
+                    {
+                        Some(payment) => {
+                            payment.invoice.state = InvoiceState::Accepted as i32;
+                            payment.invoice.amt_paid_msat += htlc.incoming_amount_msat as i64;
+                            payment.invoice.htlcs.push(InvoiceHtlc {
+                                chan_id: key.as_ref().map_or(0, |key| key.chan_id),
+                                htlc_index: key.as_ref().map_or(0, |key| key.htlc_id),
+                                amt_msat: htlc.incoming_amount_msat,
+                                expiry_height: htlc.incoming_expiry as i32,
+                                state: InvoiceHtlcState::Accepted as i32,
+                                incoming_endorsed: htlc.incoming_endorsed,
+                                custom_records: htlc.custom_records.clone(),
+                                ..Default::default()
+                            });
+                            payment
+                                .htlcs
+                                .push((key.clone().unwrap_or_default(), htlc.payment_hash));
+                            true
+                        }
+                        None => false,
+                    }
+                };
+                if !held {
+                    let response = ForwardHtlcInterceptResponse {
+                        incoming_circuit_key: key,
+                        action: ResolveHoldForwardAction::Resume as i32,
+                        ..Default::default()
+                    };
+                    if responses.send(response).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            log!("spontaneous payment interceptor stream closed");
+        });
+        holds
+    }
+
+    /// Starts expecting the payment of `hash`. Returns the channel id it must be routed over.
+    pub fn expect(&self, hash: [u8; 32], preimage: [u8; 32]) -> u64 {
+        let chan_id = hold_chan_id(&hash);
+        let invoice = Invoice {
+            r_hash: hash.to_vec(),
+            r_preimage: preimage.to_vec(),
+            state: InvoiceState::Open as i32,
+            ..Default::default()
+        };
+        self.expected.lock().unwrap().insert(
+            hash,
+            Expected {
+                chan_id,
+                preimage,
+                invoice,
+                htlcs: Vec::new(),
+            },
+        );
+        chan_id
+    }
+
+    /// The payment presented like the invoice of a hold invoice payment. Payments no longer
+    /// expected, resolved ones included, read as cancelled.
+    pub fn get(&self, hash: &[u8; 32]) -> Invoice {
+        let expected = self.expected.lock().unwrap();
+        expected.get(hash).map_or_else(
+            || Invoice {
+                r_hash: hash.to_vec(),
+                state: InvoiceState::Canceled as i32,
+                ..Default::default()
+            },
+            |payment| payment.invoice.clone(),
+        )
+    }
+
+    /// Settles the held HTLCs with the preimage, or fails them back, and stops expecting the
+    /// payment. HTLCs the preimage does not pay, as those of AMP payments, are failed back in
+    /// any case.
+    pub async fn resolve(&self, hash: &[u8; 32], settle: bool) {
+        let Some(payment) = self.expected.lock().unwrap().remove(hash) else {
+            return;
+        };
+        let (preimage, htlcs) = (payment.preimage, payment.htlcs);
+        let paid = sha256::Hash::hash(&preimage).to_byte_array();
+        for (key, htlc_hash) in htlcs {
+            let response = if settle && htlc_hash == paid {
+                ForwardHtlcInterceptResponse {
+                    incoming_circuit_key: Some(key),
+                    action: ResolveHoldForwardAction::Settle as i32,
+                    preimage: preimage.to_vec(),
+                    ..Default::default()
+                }
+            } else {
+                ForwardHtlcInterceptResponse {
+                    incoming_circuit_key: Some(key),
+                    action: ResolveHoldForwardAction::Fail as i32,
+                    failure_code: FailureCode::TemporaryChannelFailure as i32,
+                    ..Default::default()
+                }
+            };
+            self.responses.send(response).await.unwrap();
+        }
+    }
+}
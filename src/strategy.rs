@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use fedimint_tonic_lnd::lnrpc::htlc_attempt::HtlcStatus;
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{GraphTopologyUpdate, Invoice, Route};
+use fedimint_tonic_lnd::routerrpc::HtlcEvent;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+use crate::backend::{node, LightningBackend};
+use crate::events::{self, Event};
+use crate::invoice_feed::InvoiceEvent;
+use crate::mpp::Split;
+use crate::probe::LiquidityTable;
+use crate::records::{self, CustomRecords};
+use crate::spontaneous::PaymentKind;
+use crate::{gen_hash_table, Client};
+
+/// Jam payments still unresolved at the receiver after this many ticks are assumed to have failed
+/// on the way and their invoice is cancelled, so a late arrival is not held.
+const MAX_PENDING_TICKS: u64 = 60;
+/// Failed payments a holding strategy replaces before giving up on them, so a saturated route
+/// is not retried forever.
+const MAX_RESENDS: usize = 10;
+
+/// Where one of our jam payments is at, as seen from the receiver's invoice.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JamState {
+    /// Sent, not arrived at the receiver yet.
+    Pending,
+    /// Held by the receiver.
+    Held,
+    /// Cancelled before we released it, e.g. because it was about to expire, or never arrived.
+    Failed,
+    /// Cancelled by a [`Decision::Release`].
+    Released,
+    Settled,
+}
+
+impl JamState {
+    pub fn name(self) -> &'static str {
+        match self {
+            JamState::Pending => "pending",
+            JamState::Held => "held",
+            JamState::Failed => "failed",
+            JamState::Released => "released",
+            JamState::Settled => "settled",
+        }
+    }
+
+    /// Whether the payment still occupies resources along its route.
+    pub fn is_active(self) -> bool {
+        matches!(self, JamState::Pending | JamState::Held)
+    }
+}
+
+/// A payment sent by a strategy.
+#[derive(Clone)]
+pub struct JamHtlc {
+    pub hash: [u8; 32],
+    pub preimage: [u8; 32],
+    pub sender: String,
+    pub receiver: String,
+    pub amount: i64,
+    pub kind: PaymentKind,
+    /// Block height and tick at which the payment was sent.
+    pub sent_height: u32,
+    pub sent_tick: u64,
+    pub state: JamState,
+    /// Height at which the receiver's HTLC expires, known once it is held.
+    pub expiry_height: u32,
+    /// Routes of the sender's attempts when the payment was first seen held, recorded for split
+    /// payments only.
+    pub routes: Vec<Route>,
+}
+
+/// How the runtime pays for what strategies send.
+#[derive(Clone, Default)]
+pub struct PaymentOptions {
+    pub kind: PaymentKind,
+    /// Splits hold invoice payments into shards.
+    pub split: Option<Split>,
+    /// Sent to the receiver with every payment, see [`crate::records`].
+    pub custom_records: CustomRecords,
+}
+
+/// Every payment sent during a run, keyed by payment hash.
+pub type InFlightTable = HashMap<[u8; 32], JamHtlc>;
+
+/// What a strategy wants done on a tick.
+pub enum Decision {
+    /// Pays a new hold invoice of `amount` sats created by `receiver` from `sender`.
+    Send {
+        sender: String,
+        receiver: String,
+        amount: i64,
+    },
+    /// Keeps everything held as is.
+    Hold,
+    /// Cancels the invoice of a held payment, failing it back.
+    Release([u8; 32]),
+    /// Ends the run, leaving held payments in place.
+    Finish,
+}
+
+/// Node events forwarded to strategies, see [`subscribe_events`].
+pub enum StrategyEvent {
+    Htlc(HtlcEvent),
+    Graph(GraphTopologyUpdate),
+}
+
+/// Read-only view of the runtime handed to strategies.
+pub struct StrategyContext<'a> {
+    pub target: &'a str,
+    pub height: u32,
+    pub tick: u64,
+    pub in_flight: &'a InFlightTable,
+    /// Liquidity of the target's channels found by the probe phases, empty without one.
+    pub liquidity: &'a LiquidityTable,
+}
+
+impl StrategyContext<'_> {
+    /// Payments still pending or held.
+    pub fn active(&self) -> impl Iterator<Item = &JamHtlc> {
+        self.in_flight
+            .values()
+            .filter(|htlc| htlc.state.is_active())
+    }
+}
+
+/// A jamming attack, driven by [`run`] one tick at a time.
+///
+/// Strategies only decide: the runtime creates the invoices, sends and cancels payments, tracks
+/// them in the [`InFlightTable`] and follows the block height.
+#[allow(async_fn_in_trait)]
+pub trait JammingStrategy {
+    fn name(&self) -> &'static str;
+
+    /// Called once before the first tick, with access to the nodes, e.g. to look at the target's
+    /// channels.
+    async fn plan<B: LightningBackend>(
+        &mut self,
+        _nodes: &mut HashMap<String, B>,
+        _ctx: &StrategyContext<'_>,
+    ) {
+    }
+
+    /// Decides what to do this tick. Also called within a tick, with the same
+    /// [`StrategyContext::tick`], when an invoice event changes one of our payments.
+    fn tick(&mut self, ctx: &StrategyContext<'_>) -> Vec<Decision>;
+
+    /// Called when one of our payments is sent or changes state.
+    fn on_payment(&mut self, _htlc: &JamHtlc) {}
+
+    fn on_htlc(&mut self, _event: &HtlcEvent) {}
+
+    fn on_graph(&mut self, _update: &GraphTopologyUpdate) {}
+}
+
+/// Forwards the HTLC events and graph updates seen by `client` to a strategy run.
+pub async fn subscribe_events(mut client: Client) -> mpsc::UnboundedReceiver<StrategyEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut htlcs = client.subscribe_htlc_events().await;
+    let mut graph = client.subscribe_channel_graph().await;
+    let htlc_tx = tx.clone();
+    tokio::task::spawn(async move {
+        while let Ok(Some(event)) = htlcs.message().await {
+            if htlc_tx.send(StrategyEvent::Htlc(event)).is_err() {
+                break;
+            }
+        }
+    });
+    tokio::task::spawn(async move {
+        while let Ok(Some(update)) = graph.message().await {
+            if tx.send(StrategyEvent::Graph(update)).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Operator controls of running strategies, set from the dashboard.
+pub struct Controls {
+    paused: AtomicBool,
+    release_all: AtomicBool,
+    /// Tick rate relative to the strategy's own, in percent.
+    rate_percent: AtomicU64,
+}
+
+/// Apply to every strategy run in the process, see the [crate documentation](crate).
+pub static CONTROLS: Controls = Controls {
+    paused: AtomicBool::new(false),
+    release_all: AtomicBool::new(false),
+    rate_percent: AtomicU64::new(100),
+};
+
+impl Controls {
+    /// While paused, strategies are not ticked and so send nothing, held payments stay held.
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Releases every active payment of the running strategy on its next tick, or the ones a
+    /// scenario holds while it waits for blocks.
+    pub fn release_all(&self) {
+        self.release_all.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a release was asked for since the last call.
+    pub fn take_release_all(&self) -> bool {
+        self.release_all.swap(false, Ordering::Relaxed)
+    }
+
+    /// Returns once a release is asked for.
+    pub async fn wait_release_all(&self) {
+        while !self.take_release_all() {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn rate_percent(&self) -> u64 {
+        self.rate_percent.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rate_percent(&self, percent: u64) {
+        self.rate_percent
+            .store(percent.clamp(10, 1000), Ordering::Relaxed);
+    }
+}
+
+/// Tells the strategy about a new or changed payment, and everybody else through the event bus.
+fn changed<S: JammingStrategy>(strategy: &mut S, htlc: &JamHtlc) {
+    strategy.on_payment(htlc);
+    events::publish(Event::Jam(htlc.clone()));
+}
+
+/// Applies an invoice event to the payment it belongs to, if it is one of ours and still active.
+/// Returns whether the payment changed state.
+fn invoice_event<S: JammingStrategy>(
+    strategy: &mut S,
+    in_flight: &mut InFlightTable,
+    event: &InvoiceEvent,
+) -> bool {
+    let Some(htlc) = in_flight.get_mut(event.hash()) else {
+        return false;
+    };
+    if !htlc.state.is_active() {
+        return false;
+    }
+    let state = match event {
+        InvoiceEvent::HtlcAccepted { expiry_height, .. } => {
+            htlc.expiry_height = *expiry_height;
+            return false;
+        }
+        InvoiceEvent::Accepted { .. } => JamState::Held,
+        InvoiceEvent::Settled { .. } => JamState::Settled,
+        InvoiceEvent::Cancelled { .. } => JamState::Failed,
+    };
+    if state == htlc.state {
+        return false;
+    }
+    htlc.state = state;
+    changed(strategy, htlc);
+    true
+}
+
+/// What ended the wait for the next tick.
+#[derive(Clone, Copy)]
+enum Wake {
+    /// The tick is due.
+    Tick,
+    /// An invoice event changed the state of this payment.
+    Changed([u8; 32]),
+}
+
+/// Sleeps until `deadline`, or until an invoice event from an [`crate::invoice_feed`] changes
+/// the state of one of our payments so the strategy can react right away.
+async fn wait_tick<S: JammingStrategy>(
+    strategy: &mut S,
+    in_flight: &mut InFlightTable,
+    bus: &mut broadcast::Receiver<Event>,
+    deadline: Instant,
+) -> Wake {
+    loop {
+        let event = tokio::select! {
+            _ = sleep_until(deadline) => return Wake::Tick,
+            event = bus.recv() => event,
+        };
+        match event {
+            Ok(Event::Invoice(event)) => {
+                if invoice_event(strategy, in_flight, &event) {
+                    return Wake::Changed(event.hash().try_into().unwrap());
+                }
+            }
+            // missed updates are picked up by polling the invoices on the next tick
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => {
+                sleep_until(deadline).await;
+                return Wake::Tick;
+            }
+        }
+    }
+}
+
+/// Prints the custom records the receiver saw on each held htlc of a payment, if any.
+fn log_records(receiver: &str, hash: &[u8; 32], invoice: &Invoice) {
+    for htlc in invoice
+        .htlcs
+        .iter()
+        .filter(|htlc| !htlc.custom_records.is_empty())
+    {
+        log!(
+            "{} received {} over {} with records {}",
+            receiver,
+            hex::encode(hash),
+            htlc.chan_id,
+            records::format(&htlc.custom_records)
+        );
+    }
+}
+
+/// The receiver's view of one of our payments: the invoice it pays, or for spontaneous payments
+/// the way the receiver holds it.
+pub async fn lookup<B: LightningBackend>(
+    receiver: &mut B,
+    hash: [u8; 32],
+    kind: PaymentKind,
+) -> Invoice {
+    match kind {
+        PaymentKind::Invoice => receiver.get_invoice(hash.to_vec()).await,
+        PaymentKind::Keysend | PaymentKind::Amp => receiver.get_spontaneous(hash).await,
+    }
+}
+
+/// Fails back one of our payments at its receiver.
+pub async fn cancel<B: LightningBackend>(receiver: &mut B, hash: [u8; 32], kind: PaymentKind) {
+    match kind {
+        PaymentKind::Invoice => receiver.cancel_invoice(hash.to_vec()).await,
+        PaymentKind::Keysend | PaymentKind::Amp => receiver.resolve_spontaneous(hash, false).await,
+    }
+}
+
+/// Runs `strategy` against `nodes` one tick every `tick` until it decides to finish, and returns
+/// every payment it sent. Payments are made as `payment` says, and strategies find the
+/// `liquidity` estimates in their context. Between ticks, invoice events published on the bus
+/// that change one of our payments wake the strategy early, without starting a new tick.
+pub async fn run<S: JammingStrategy, B: LightningBackend>(
+    strategy: &mut S,
+    nodes: &mut HashMap<String, B>,
+    target: &str,
+    tick: Duration,
+    payment: PaymentOptions,
+    liquidity: &LiquidityTable,
+    mut events: Option<&mut mpsc::UnboundedReceiver<StrategyEvent>>,
+) -> InFlightTable {
+    log!("running {} strategy", strategy.name());
+    let mut bus = events::subscribe();
+    let mut in_flight = InFlightTable::new();
+    let mut height = nodes.values_mut().next().unwrap().get_block_height().await;
+    let ctx = StrategyContext {
+        target,
+        height,
+        tick: 0,
+        in_flight: &in_flight,
+        liquidity,
+    };
+    strategy.plan(nodes, &ctx).await;
+
+    let mut tick_count = 0;
+    let mut deadline = None;
+    let mut wake = Wake::Tick;
+    loop {
+        if let Some(events) = events.as_mut() {
+            while let Ok(event) = events.try_recv() {
+                match event {
+                    StrategyEvent::Htlc(event) => strategy.on_htlc(&event),
+                    StrategyEvent::Graph(update) => strategy.on_graph(&update),
+                }
+            }
+        }
+
+        // every payment is polled on ticks, only the changed one on early wakes
+        let release_all = CONTROLS.take_release_all();
+        let polled = |htlc: &&mut JamHtlc| match wake {
+            Wake::Tick => true,
+            Wake::Changed(hash) => release_all || htlc.hash == hash,
+        };
+        for htlc in in_flight
+            .values_mut()
+            .filter(|htlc| htlc.state.is_active())
+            .filter(polled)
+        {
+            let receiver = node(nodes, &htlc.receiver);
+            if release_all {
+                cancel(receiver, htlc.hash, htlc.kind).await;
+                htlc.state = JamState::Released;
+                changed(strategy, htlc);
+                continue;
+            }
+            let invoice = lookup(receiver, htlc.hash, htlc.kind).await;
+            if let Some(accepted) = invoice.htlcs.first() {
+                htlc.expiry_height = accepted.expiry_height as u32;
+            }
+            let state = match InvoiceState::try_from(invoice.state) {
+                Ok(InvoiceState::Accepted) => JamState::Held,
+                Ok(InvoiceState::Canceled) => JamState::Failed,
+                Ok(InvoiceState::Settled) => JamState::Settled,
+                _ if tick_count - htlc.sent_tick > MAX_PENDING_TICKS => {
+                    cancel(receiver, htlc.hash, htlc.kind).await;
+                    JamState::Failed
+                }
+                _ => JamState::Pending,
+            };
+            if state != htlc.state {
+                if state == JamState::Held {
+                    log_records(&htlc.receiver, &htlc.hash, &invoice);
+                }
+                htlc.state = state;
+                changed(strategy, htlc);
+            }
+        }
+
+        // routes are recorded before a release fails the attempts
+        if payment.split.is_some() {
+            for htlc in in_flight
+                .values_mut()
+                .filter(|htlc| htlc.state == JamState::Held && htlc.routes.is_empty())
+            {
+                htlc.routes = node(nodes, &htlc.sender)
+                    .payment_htlcs(htlc.hash)
+                    .await
+                    .into_iter()
+                    .filter(|attempt| attempt.status != HtlcStatus::Failed as i32)
+                    .filter_map(|attempt| attempt.route)
+                    .collect();
+            }
+        }
+
+        if let Wake::Tick = wake {
+            height = nodes.values_mut().next().unwrap().get_block_height().await;
+            events::publish(Event::Height(height));
+        }
+        let tick = tick * 100 / CONTROLS.rate_percent() as u32;
+        if CONTROLS.is_paused() {
+            sleep(tick).await;
+            wake = Wake::Tick;
+            continue;
+        }
+        let ctx = StrategyContext {
+            target,
+            height,
+            tick: tick_count,
+            in_flight: &in_flight,
+            liquidity,
+        };
+        for decision in strategy.tick(&ctx) {
+            match decision {
+                Decision::Send {
+                    sender,
+                    receiver,
+                    amount,
+                } => {
+                    let (preimage, hash) = gen_hash_table(1)[0];
+                    if payment.kind == PaymentKind::Invoice {
+                        let invoice = node(nodes, &receiver)
+                            .add_hold_invoice(hash.to_vec(), amount)
+                            .await;
+                        let sender = node(nodes, &sender);
+                        sender
+                            .send_payment_with(
+                                invoice,
+                                payment.split,
+                                payment.custom_records.clone(),
+                            )
+                            .await;
+                    } else {
+                        let holder = node(nodes, &receiver);
+                        let chan_id = holder.expect_spontaneous(hash, preimage).await;
+                        let pubkey = holder.get_pubkey().await;
+                        node(nodes, &sender)
+                            .send_spontaneous(
+                                pubkey,
+                                chan_id,
+                                amount,
+                                preimage,
+                                payment.kind == PaymentKind::Amp,
+                                payment.custom_records.clone(),
+                            )
+                            .await;
+                    }
+                    let htlc = JamHtlc {
+                        hash,
+                        preimage,
+                        sender,
+                        receiver,
+                        amount,
+                        kind: payment.kind,
+                        sent_height: height,
+                        sent_tick: tick_count,
+                        state: JamState::Pending,
+                        expiry_height: 0,
+                        routes: Vec::new(),
+                    };
+                    changed(strategy, &htlc);
+                    in_flight.insert(hash, htlc);
+                }
+                Decision::Hold => {}
+                Decision::Release(hash) => {
+                    let Some(htlc) = in_flight.get_mut(&hash) else {
+                        continue;
+                    };
+                    if htlc.state.is_active() {
+                        cancel(node(nodes, &htlc.receiver), hash, htlc.kind).await;
+                        htlc.state = JamState::Released;
+                        changed(strategy, htlc);
+                    }
+                }
+                Decision::Finish => return in_flight,
+            }
+        }
+        let due = *deadline.get_or_insert_with(|| Instant::now() + tick);
+        wake = wait_tick(strategy, &mut in_flight, &mut bus, due).await;
+        if let Wake::Tick = wake {
+            tick_count += 1;
+            deadline = None;
+        }
+    }
+}
+
+/// Sends `htlcs` held payments at once and keeps them held, re-sending up to [`MAX_RESENDS`] of
+/// those that fail before the run finishes.
+struct HoldJam {
+    sender: String,
+    receiver: String,
+    htlcs: usize,
+    amount: i64,
+    retries: usize,
+    started: bool,
+    resend: usize,
+}
+
+impl HoldJam {
+    fn new(sender: String, receiver: String, htlcs: usize, amount: i64) -> Self {
+        HoldJam {
+            sender,
+            receiver,
+            htlcs,
+            amount,
+            retries: MAX_RESENDS,
+            started: false,
+            resend: 0,
+        }
+    }
+
+    fn tick(&mut self, ctx: &StrategyContext<'_>) -> Vec<Decision> {
+        let count = if !self.started {
+            self.started = true;
+            self.htlcs
+        } else {
+            std::mem::take(&mut self.resend)
+        };
+        if count == 0 && ctx.active().all(|htlc| htlc.state == JamState::Held) {
+            return vec![Decision::Finish];
+        }
+        let mut decisions: Vec<Decision> = (0..count)
+            .map(|_| Decision::Send {
+                sender: self.sender.clone(),
+                receiver: self.receiver.clone(),
+                amount: self.amount,
+            })
+            .collect();
+        decisions.push(Decision::Hold);
+        decisions
+    }
+
+    fn on_payment(&mut self, htlc: &JamHtlc) {
+        if htlc.state == JamState::Failed && self.retries > 0 {
+            self.retries -= 1;
+            self.resend += 1;
+        }
+    }
+}
+
+/// Many small HTLCs exhausting the target's HTLC slots.
+pub struct SlotJam(HoldJam);
+
+impl SlotJam {
+    pub fn new(sender: String, receiver: String, htlcs: usize, amount: i64) -> Self {
+        SlotJam(HoldJam::new(sender, receiver, htlcs, amount))
+    }
+}
+
+impl JammingStrategy for SlotJam {
+    fn name(&self) -> &'static str {
+        "slots"
+    }
+
+    fn tick(&mut self, ctx: &StrategyContext<'_>) -> Vec<Decision> {
+        self.0.tick(ctx)
+    }
+
+    fn on_payment(&mut self, htlc: &JamHtlc) {
+        self.0.on_payment(htlc)
+    }
+}
+
+/// Few large HTLCs locking up the target's liquidity.
+pub struct LiquidityJam(HoldJam);
+
+impl LiquidityJam {
+    pub fn new(sender: String, receiver: String, htlcs: usize, amount: i64) -> Self {
+        LiquidityJam(HoldJam::new(sender, receiver, htlcs, amount))
+    }
+}
+
+impl JammingStrategy for LiquidityJam {
+    fn name(&self) -> &'static str {
+        "liquidity"
+    }
+
+    fn tick(&mut self, ctx: &StrategyContext<'_>) -> Vec<Decision> {
+        self.0.tick(ctx)
+    }
+
+    fn on_payment(&mut self, htlc: &JamHtlc) {
+        self.0.on_payment(htlc)
+    }
+}
+
+/// Held payments from a node to itself, routed out through one of its channels and back
+/// through another, so a single node jams both directions of the target's channels. Needs
+/// `allow_self_payment`, which [`Client::send_payment`] sets.
+pub struct CircularJam(HoldJam);
+
+impl CircularJam {
+    pub fn new(node: String, htlcs: usize, amount: i64) -> Self {
+        CircularJam(HoldJam::new(node.clone(), node, htlcs, amount))
+    }
+}
+
+impl JammingStrategy for CircularJam {
+    fn name(&self) -> &'static str {
+        "circular"
+    }
+
+    fn tick(&mut self, ctx: &StrategyContext<'_>) -> Vec<Decision> {
+        self.0.tick(ctx)
+    }
+
+    fn on_payment(&mut self, htlc: &JamHtlc) {
+        self.0.on_payment(htlc)
+    }
+}
+
+/// Sends `per_tick` payments every tick for `ticks` ticks and releases each one as soon as it
+/// is held. Slots stay occupied while every HTLC resolves quickly, so slow resolution is never
+/// held against us.
+pub struct FastJam {
+    sender: String,
+    receiver: String,
+    per_tick: usize,
+    amount: i64,
+    ticks: u64,
+    /// Last tick payments were sent on, strategies being ticked again when payments change.
+    sent_tick: Option<u64>,
+    release: Vec<[u8; 32]>,
+}
+
+impl FastJam {
+    pub fn new(sender: String, receiver: String, per_tick: usize, amount: i64, ticks: u64) -> Self {
+        FastJam {
+            sender,
+            receiver,
+            per_tick,
+            amount,
+            ticks,
+            sent_tick: None,
+            release: Vec::new(),
+        }
+    }
+}
+
+impl JammingStrategy for FastJam {
+    fn name(&self) -> &'static str {
+        "fast"
+    }
+
+    fn tick(&mut self, ctx: &StrategyContext<'_>) -> Vec<Decision> {
+        let mut decisions: Vec<Decision> = self.release.drain(..).map(Decision::Release).collect();
+        if ctx.tick < self.ticks {
+            if self.sent_tick == Some(ctx.tick) {
+                return decisions;
+            }
+            self.sent_tick = Some(ctx.tick);
+            decisions.extend((0..self.per_tick).map(|_| Decision::Send {
+                sender: self.sender.clone(),
+                receiver: self.receiver.clone(),
+                amount: self.amount,
+            }));
+        } else if decisions.is_empty() && ctx.active().next().is_none() {
+            decisions.push(Decision::Finish);
+        }
+        decisions
+    }
+
+    fn on_payment(&mut self, htlc: &JamHtlc) {
+        if htlc.state == JamState::Held {
+            self.release.push(htlc.hash);
+        }
+    }
+}
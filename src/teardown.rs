@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use fedimint_tonic_lnd::lnrpc::close_status_update::Update;
+use fedimint_tonic_lnd::tonic::Status;
+
+use crate::channel_point::{self, ChannelPoint};
+use crate::funding::CONF_TARGET;
+use crate::Client;
+
+/// Follows a close until its closing transaction confirms, returns that transaction's txid.
+async fn follow_close(
+    client: &mut Client,
+    point: &ChannelPoint,
+    sat_per_vbyte: u64,
+    force: bool,
+) -> Result<String, Status> {
+    let mut updates = client.close_channel(*point, sat_per_vbyte, force).await?;
+    while let Some(update) = updates.message().await? {
+        match update.update {
+            Some(Update::ClosePending(pending)) => log!(
+                "{}: closing transaction {} published",
+                point,
+                hex::encode(channel_point::txid_from_rpc(&pending.txid))
+            ),
+            Some(Update::ChanClose(close)) => {
+                return Ok(hex::encode(channel_point::txid_from_rpc(
+                    &close.closing_txid,
+                )))
+            }
+            None => {}
+        }
+    }
+    Err(Status::unavailable("close stream ended"))
+}
+
+/// How a channel close ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Outcome {
+    Closed,
+    ForceClosed,
+    /// Neither close went through, the channel may still be open.
+    Failed,
+}
+
+/// Closes a channel cooperatively, or by force if that fails.
+async fn close(
+    mut client: Client,
+    node: String,
+    point: ChannelPoint,
+    sat_per_vbyte: u64,
+) -> Outcome {
+    let status = match follow_close(&mut client, &point, sat_per_vbyte, false).await {
+        Ok(txid) => {
+            log!("{}: closed {} in {}", node, point, txid);
+            return Outcome::Closed;
+        }
+        Err(status) => status,
+    };
+    log!(
+        "{}: cooperative close of {} failed ({}), force closing",
+        node,
+        point,
+        status.message()
+    );
+    match follow_close(&mut client, &point, sat_per_vbyte, true).await {
+        Ok(txid) => {
+            log!("{}: force closed {} in {}", node, point, txid);
+            Outcome::ForceClosed
+        }
+        Err(status) => {
+            log!("{}: could not close {}: {}", node, point, status.message());
+            Outcome::Failed
+        }
+    }
+}
+
+/// Closes every channel of `channels`, (node, channel point) pairs as read from a
+/// [`crate::channel_log`], that is still open. Closes are cooperative at a fee rate estimated
+/// for [`CONF_TARGET`] blocks, with a force close as fallback, and run concurrently until their
+/// closing transactions confirm. Then sends each node's remaining wallet balance to
+/// `sweep_address`, if given. Returns the channels that could not be closed, which stay in the
+/// channel log so that the next teardown retries them.
+pub async fn teardown(
+    mut nodes: HashMap<String, Client>,
+    channels: Vec<(String, ChannelPoint)>,
+    sweep_address: Option<&str>,
+) -> Vec<(String, ChannelPoint)> {
+    let mut closes = Vec::new();
+    for (node, point) in channels {
+        let Some(client) = nodes.get_mut(&node) else {
+            log!("{}: unknown node, not closing {}", node, point);
+            continue;
+        };
+        let open = client
+            .list_channels()
+            .await
+            .iter()
+            .any(|channel| channel.channel_point.parse() == Ok(point));
+        if !open {
+            log!("{}: {} is not open, skipping", node, point);
+            continue;
+        }
+        let sat_per_vbyte = client.estimate_fee_rate(CONF_TARGET).await;
+        closes.push((
+            node.clone(),
+            point,
+            tokio::task::spawn(close(client.clone(), node, point, sat_per_vbyte)),
+        ));
+    }
+    let mut forced = false;
+    let mut failed = Vec::new();
+    for (node, point, close) in closes {
+        match close.await.unwrap() {
+            Outcome::Closed => {}
+            Outcome::ForceClosed => forced = true,
+            Outcome::Failed => failed.push((node, point)),
+        }
+    }
+    if forced {
+        log!("force closed funds stay timelocked and are not part of the sweep");
+    }
+    if !failed.is_empty() {
+        log!(
+            "{} channels could not be closed, run teardown again to retry:",
+            failed.len()
+        );
+        for (node, point) in &failed {
+            log!("  {}: {}", node, point);
+        }
+    }
+
+    let Some(address) = sweep_address else {
+        return failed;
+    };
+    for (node, client) in nodes.iter_mut() {
+        if client.wallet_balance().await == 0 {
+            continue;
+        }
+        let sat_per_vbyte = client.estimate_fee_rate(CONF_TARGET).await;
+        let txid = client.sweep(String::from(address), sat_per_vbyte).await;
+        log!("{}: swept wallet to {} in {}", node, address, txid);
+    }
+    failed
+}
@@ -0,0 +1,40 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Shared clock for an experiment run, so that records written by different
+/// background tasks can be lined up against each other.
+#[derive(Clone)]
+pub struct Timeline {
+    start: Instant,
+    phase: Arc<Mutex<String>>,
+}
+
+impl Timeline {
+    pub fn start() -> Self {
+        Timeline {
+            start: Instant::now(),
+            phase: Arc::new(Mutex::new(String::from("setup"))),
+        }
+    }
+
+    pub fn elapsed_ms(&self) -> u128 {
+        self.start.elapsed().as_millis()
+    }
+
+    /// Name of the experiment phase we are currently in, e.g. "setup", "jam" or "release".
+    pub fn phase(&self) -> String {
+        self.phase.lock().unwrap().clone()
+    }
+
+    pub fn set_phase(&self, phase: &str) {
+        log!("[{} ms] entering phase {}", self.elapsed_ms(), phase);
+        *self.phase.lock().unwrap() = String::from(phase);
+    }
+}
+
+pub fn unix_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
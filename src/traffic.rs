@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use fedimint_tonic_lnd::lnrpc::failure::FailureCode;
+use fedimint_tonic_lnd::lnrpc::payment::PaymentStatus;
+use fedimint_tonic_lnd::lnrpc::PaymentFailureReason;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Deserializer};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::timeline::{unix_ms, Timeline};
+use crate::Client;
+
+const HEADER: &str = "unix_ms,elapsed_ms,phase,sender,receiver,payment_hash,amount_sat,status,\
+failure_reason,failure_code,failure_source_index,attempts,duration_ms";
+
+/// Distribution honest payment amounts are drawn from, in sats.
+#[derive(Clone, Copy)]
+pub enum AmountDistribution {
+    Fixed(u64),
+    Uniform {
+        min: u64,
+        max: u64,
+    },
+    /// exp(N(mu, sigma)), which is close to what real payment sizes look like.
+    LogNormal {
+        mu: f64,
+        sigma: f64,
+    },
+}
+
+impl AmountDistribution {
+    /// Parses `fixed:<sat>`, `uniform:<min>:<max>` or `lognormal:<mu>:<sigma>`, checking that
+    /// the distribution can be sampled.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid amount distribution: {}", s);
+        let parts: Vec<&str> = s.split(':').collect();
+        let distribution = match parts[..] {
+            ["fixed", amount] => AmountDistribution::Fixed(amount.parse().map_err(|_| invalid())?),
+            ["uniform", min, max] => AmountDistribution::Uniform {
+                min: min.parse().map_err(|_| invalid())?,
+                max: max.parse().map_err(|_| invalid())?,
+            },
+            ["lognormal", mu, sigma] => AmountDistribution::LogNormal {
+                mu: mu.parse().map_err(|_| invalid())?,
+                sigma: sigma.parse().map_err(|_| invalid())?,
+            },
+            _ => return Err(invalid()),
+        };
+        let valid = match distribution {
+            AmountDistribution::Fixed(amount) => amount > 0,
+            AmountDistribution::Uniform { min, max } => min > 0 && min <= max,
+            AmountDistribution::LogNormal { mu, sigma } => {
+                mu.is_finite() && sigma.is_finite() && sigma >= 0.0
+            }
+        };
+        if valid {
+            Ok(distribution)
+        } else {
+            Err(invalid())
+        }
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
+        match *self {
+            AmountDistribution::Fixed(amount) => amount,
+            AmountDistribution::Uniform { min, max } => rng.gen_range(min..=max),
+            AmountDistribution::LogNormal { mu, sigma } => {
+                // Box-Muller
+                let u1: f64 = 1.0 - rng.gen::<f64>();
+                let u2: f64 = rng.gen();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                ((mu + sigma * z).exp() as u64).max(1)
+            }
+        }
+    }
+}
+
+/// Checks that `rate` payments per second is a rate payments can be sent at.
+pub fn check_rate(rate: f64) -> Result<f64, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!("invalid traffic rate: {}", rate))
+    }
+}
+
+/// Deserializes an optional rate, see [`check_rate`].
+pub fn deserialize_rate<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(check_rate)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an optional amount distribution written as [`AmountDistribution::parse`] takes
+/// it.
+pub fn deserialize_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<AmountDistribution>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|amount| AmountDistribution::parse(&amount))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Number of honest payments sent and failed.
+#[derive(Clone, Copy, Default)]
+pub struct PhaseStats {
+    pub sent: u64,
+    pub failed: u64,
+}
+
+impl PhaseStats {
+    pub fn failure_rate(&self) -> f64 {
+        if self.sent == 0 {
+            0.0
+        } else {
+            self.failed as f64 / self.sent as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct TrafficState {
+    phases: HashMap<String, PhaseStats>,
+    in_flight: u64,
+    stopped: bool,
+}
+
+/// Honest payment outcomes keyed by the experiment phase the payment was sent in.
+#[derive(Clone, Default)]
+pub struct TrafficStats(Arc<Mutex<TrafficState>>);
+
+impl TrafficStats {
+    fn send(&self) {
+        self.0.lock().unwrap().in_flight += 1;
+    }
+
+    fn record(&self, phase: String, failed: bool) {
+        let mut state = self.0.lock().unwrap();
+        state.in_flight -= 1;
+        let stats = state.phases.entry(phase).or_default();
+        stats.sent += 1;
+        if failed {
+            stats.failed += 1;
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.0.lock().unwrap().stopped
+    }
+
+    /// Stops sending honest payments and waits for those in flight to succeed or fail, so that
+    /// the stats no longer change.
+    pub async fn stop(&self) {
+        self.0.lock().unwrap().stopped = true;
+        while self.0.lock().unwrap().in_flight > 0 {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn phase(&self, phase: &str) -> PhaseStats {
+        self.0
+            .lock()
+            .unwrap()
+            .phases
+            .get(phase)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn total(&self) -> PhaseStats {
+        self.0
+            .lock()
+            .unwrap()
+            .phases
+            .values()
+            .fold(PhaseStats::default(), |total, stats| PhaseStats {
+                sent: total.sent + stats.sent,
+                failed: total.failed + stats.failed,
+            })
+    }
+}
+
+pub struct TrafficConfig {
+    /// Mean number of payments per second, summed over all sender/receiver pairs. Must pass
+    /// [`check_rate`].
+    pub rate_per_sec: f64,
+    pub amount: AmountDistribution,
+    /// Forces payments to reach the receiver through this node, usually the target.
+    pub last_hop_pubkey: Option<String>,
+    pub endorsed: bool,
+    pub timeout_seconds: i32,
+}
+
+/// Background task sending honest payments between `pairs` of (sender, receiver) nodes with
+/// Poisson arrivals, recording the outcome of every payment, and why it failed, to `path` and
+/// `stats`, until [`TrafficStats::stop`] is called.
+pub fn spawn_traffic(
+    pairs: Vec<(&'static str, Client, &'static str, Client)>,
+    config: TrafficConfig,
+    path: &str,
+    timeline: Timeline,
+    stats: TrafficStats,
+) -> tokio::task::JoinHandle<()> {
+    let out = Arc::new(Mutex::new(BufWriter::new(File::create(path).unwrap())));
+    writeln!(out.lock().unwrap(), "{}", HEADER).unwrap();
+
+    tokio::task::spawn(async move {
+        let mut pairs = pairs;
+        loop {
+            // exponential inter-arrival times give a Poisson process
+            let u: f64 = 1.0 - thread_rng().gen::<f64>();
+            sleep(Duration::from_secs_f64(-u.ln() / config.rate_per_sec)).await;
+            if stats.stopped() {
+                break;
+            }
+
+            let (amount, pair) = {
+                let mut rng = thread_rng();
+                (
+                    config.amount.sample(&mut rng),
+                    rng.gen_range(0..pairs.len()),
+                )
+            };
+            let (sender_name, sender, receiver_name, receiver) = &mut pairs[pair];
+            let (payment_request, hash) = receiver.add_invoice(amount as i64).await;
+            let mut sender = sender.clone();
+            let (sender_name, receiver_name) = (*sender_name, *receiver_name);
+            let last_hop = config
+                .last_hop_pubkey
+                .as_ref()
+                .map(|pubkey| hex::decode(pubkey).unwrap())
+                .unwrap_or_default();
+            let (endorsed, timeout_seconds) = (config.endorsed, config.timeout_seconds);
+            let out = out.clone();
+            let timeline = timeline.clone();
+            let stats = stats.clone();
+            let phase = timeline.phase();
+            stats.send();
+
+            tokio::task::spawn(async move {
+                let started = Instant::now();
+                // an RPC error is recorded as a failed payment, so that `stop` is not left
+                // waiting on it
+                let result = sender
+                    .pay_invoice(payment_request, last_hop, endorsed, timeout_seconds)
+                    .await;
+                let (status, reason, code, source_index, attempts, failed) = match &result {
+                    Ok(payment) => {
+                        let failure = payment
+                            .htlcs
+                            .iter()
+                            .rev()
+                            .find_map(|htlc| htlc.failure.clone());
+                        let (code, source_index) = match failure {
+                            Some(failure) => (
+                                FailureCode::try_from(failure.code)
+                                    .map_or("UNKNOWN", |code| code.as_str_name()),
+                                failure.failure_source_index.to_string(),
+                            ),
+                            None => ("", String::new()),
+                        };
+                        (
+                            PaymentStatus::try_from(payment.status)
+                                .map_or("UNKNOWN", |status| status.as_str_name())
+                                .to_string(),
+                            PaymentFailureReason::try_from(payment.failure_reason)
+                                .map_or("UNKNOWN", |reason| reason.as_str_name())
+                                .to_string(),
+                            code,
+                            source_index,
+                            payment.htlcs.len(),
+                            payment.status != PaymentStatus::Succeeded as i32,
+                        )
+                    }
+                    Err(error) => (
+                        "ERROR".to_string(),
+                        format!("{:?}", error.code()),
+                        "",
+                        String::new(),
+                        0,
+                        true,
+                    ),
+                };
+                stats.record(phase.clone(), failed);
+                if let Err(error) = &result {
+                    log!(
+                        "honest payment {} failed: {}",
+                        hex::encode(&hash),
+                        error.message()
+                    );
+                } else if failed {
+                    log!(
+                        "honest payment {} failed: {} {}",
+                        hex::encode(&hash),
+                        reason,
+                        code
+                    );
+                }
+
+                let mut out = out.lock().unwrap();
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    unix_ms(),
+                    timeline.elapsed_ms(),
+                    phase,
+                    sender_name,
+                    receiver_name,
+                    hex::encode(&hash),
+                    amount,
+                    status,
+                    reason,
+                    code,
+                    source_index,
+                    attempts,
+                    started.elapsed().as_millis(),
+                )
+                .unwrap();
+                out.flush().unwrap();
+            });
+        }
+    })
+}
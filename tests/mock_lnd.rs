@@ -0,0 +1,122 @@
+//! Runs the [`Client`] calls jammy makes against the mock LND node of `fedimint_tonic_lnd`.
+
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{LightningNode, NodeInfo};
+use fedimint_tonic_lnd::mock::{MockLnd, MockServer};
+use jammy::backend::LightningBackend;
+use jammy::events::{self, Event};
+use jammy::invoice_feed::{spawn_invoice_feed, InvoiceEvent};
+use jammy::setup::ChannelOptions;
+use jammy::{gen_hash_table, Client};
+use tokio::time::{sleep, timeout, Duration};
+
+const PEER: &str = "02bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+async fn start(name: &str) -> (MockLnd, MockServer, Client) {
+    let mock = MockLnd::new();
+    let dir = std::env::temp_dir().join(format!("jammy-mock-{}-{}", name, std::process::id()));
+    let server = mock.serve(dir).await.unwrap();
+    let client = Client::new(server.connect().await.unwrap());
+    (mock, server, client)
+}
+
+#[tokio::test]
+async fn hold_invoice_payment_is_settled() {
+    let (mock, _server, mut client) = start("settle").await;
+    mock.state().info.identity_pubkey = String::from(PEER);
+    mock.state().info.block_height = 800;
+    assert_eq!(client.get_pubkey().await, PEER);
+    assert_eq!(client.get_block_height().await, 800);
+
+    let (preimage, hash) = gen_hash_table(1)[0];
+    let payment_request = client.add_hold_invoice(hash.to_vec(), 1000).await;
+    client.send_payment(payment_request).await;
+    // the mock does not route, the payment arriving is up to us
+    mock.state().invoices.get_mut(&hash[..]).unwrap().state = InvoiceState::Accepted as i32;
+    client.settle_invoice(preimage.to_vec()).await;
+
+    let invoice = client.get_invoice(hash.to_vec()).await;
+    assert_eq!(invoice.state, InvoiceState::Settled as i32);
+    assert_eq!(invoice.r_preimage, preimage);
+    let calls = mock.state().calls.clone();
+    for call in [
+        "AddHoldInvoice",
+        "SendPaymentV2",
+        "SettleInvoice",
+        "LookupInvoice",
+    ] {
+        assert!(calls.contains(&call), "{} not called", call);
+    }
+}
+
+#[tokio::test]
+async fn opened_channels_are_listed() {
+    let (_mock, _server, mut client) = start("channels").await;
+    let point = client
+        .open_channel(
+            String::from(PEER),
+            500_000,
+            100_000,
+            &ChannelOptions::default(),
+        )
+        .await;
+    assert_eq!(
+        client.channels_with(String::from(PEER)).await,
+        vec![(point, 500_000)]
+    );
+    assert_eq!(client.channel_id(point).await, Some(1));
+    assert_eq!(client.pending_open_channels().await, 0);
+}
+
+#[tokio::test]
+async fn invoice_feed_reports_cancelled_invoices() {
+    let (mock, _server, mut client) = start("feed").await;
+    let mut bus = events::subscribe();
+    let _feed = spawn_invoice_feed(String::from("bob"), client.clone());
+    while !mock.state().calls.contains(&"SubscribeInvoices") {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    let (_, hash) = gen_hash_table(1)[0];
+    client.add_hold_invoice(hash.to_vec(), 1000).await;
+    client.cancel_invoice(hash.to_vec()).await;
+
+    let cancelled = timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(Event::Invoice(InvoiceEvent::Cancelled {
+                node,
+                hash: event_hash,
+            })) = bus.recv().await
+            {
+                if event_hash == hash {
+                    return node;
+                }
+            }
+        }
+    })
+    .await
+    .expect("no cancellation reported");
+    assert_eq!(cancelled, "bob");
+}
+
+#[tokio::test]
+async fn connected_peer_without_addresses_counts_as_connected() {
+    let (mock, _server, mut client) = start("peers").await;
+    mock.state().nodes.insert(
+        String::from(PEER),
+        NodeInfo {
+            node: Some(LightningNode {
+                pub_key: String::from(PEER),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    assert!(!LightningBackend::connect_peer(&mut client, String::from(PEER)).await);
+
+    // the peer connected to us, or we did at an address it no longer advertises
+    let host = String::from("127.0.0.1:9735");
+    assert!(client.connect_peer(String::from(PEER), host.clone()).await);
+    assert!(client.connect_peer(String::from(PEER), host).await);
+    assert!(LightningBackend::connect_peer(&mut client, String::from(PEER)).await);
+}
@@ -0,0 +1,232 @@
+//! Loads and runs scenarios through the library API, against the simulator.
+
+use fedimint_tonic_lnd::routerrpc::MissionControlConfig;
+use jammy::backend::LightningBackend;
+use jammy::mission_control::{self, Snapshot};
+use jammy::scenario::{self, LoadError, Scenario};
+use jammy::sim::{self, Simulator};
+use jammy::timeline::Timeline;
+use jammy::traffic::TrafficStats;
+
+/// Slot jam of the sample scenario, without the honest traffic the simulator lacks.
+const SLOT_JAM: &str = r#"
+name = "slot jam"
+
+[[phase]]
+kind = "setup"
+channels = [
+    { node = "alice", peer_index = 1, amount = 500000 },
+    { node = "bob", peer_index = 2, amount = 500000, push = 250000 },
+]
+
+[[phase]]
+kind = "jam"
+mode = "slots"
+sender = "alice"
+receiver = "bob"
+htlcs = 483
+amount = 1
+tick_ms = 1
+
+[[phase]]
+kind = "release"
+
+[[assert]]
+metric = "jam_htlcs_held"
+min = 483
+"#;
+
+/// A target with three peers, and alice and bob funded to open channels with them.
+fn network() -> (String, std::collections::HashMap<String, sim::SimNode>) {
+    let sim = Simulator::new();
+    let target = sim::pubkey("target");
+    for i in 0..3 {
+        sim.add_channel(
+            &target,
+            &sim::pubkey(&format!("peer{}", i)),
+            1_000_000,
+            500_000,
+        );
+    }
+    let nodes = ["alice", "bob"]
+        .into_iter()
+        .map(|name| {
+            sim.fund(&sim::pubkey(name), 10_000_000);
+            (String::from(name), sim.node(&sim::pubkey(name)))
+        })
+        .collect();
+    (target, nodes)
+}
+
+#[test]
+fn sample_scenarios_load() {
+    for entry in std::fs::read_dir("scenarios").unwrap() {
+        let path = entry.unwrap().path();
+        if let Err(err) = Scenario::load(path.to_str().unwrap()) {
+            panic!("{}: {}", path.display(), err);
+        }
+    }
+}
+
+#[test]
+fn missing_scenario_is_an_error() {
+    assert!(matches!(
+        Scenario::load("scenarios/missing.toml"),
+        Err(LoadError::Read(_))
+    ));
+}
+
+#[test]
+fn invalid_scenario_is_an_error() {
+    let invalid = SLOT_JAM.replace("mode = \"slots\"", "mode = \"bogus\"");
+    assert!(matches!(
+        Scenario::parse(&invalid),
+        Err(LoadError::Parse(_))
+    ));
+
+    let no_receiver = SLOT_JAM.replace("receiver = \"bob\"\n", "");
+    assert!(matches!(
+        Scenario::parse(&no_receiver),
+        Err(LoadError::Invalid(_))
+    ));
+    // circular jams pay the sender itself
+    let circular = no_receiver.replace("mode = \"slots\"", "mode = \"circular\"");
+    assert!(Scenario::parse(&circular).is_ok());
+
+    let liquidity = SLOT_JAM.replace("mode = \"slots\"", "mode = \"liquidity\"");
+    assert!(Scenario::parse(&liquidity).is_ok());
+    let no_amount = liquidity.replace("amount = 1\n", "");
+    assert!(matches!(
+        Scenario::parse(&no_amount),
+        Err(LoadError::Invalid(_))
+    ));
+}
+
+#[test]
+fn invalid_traffic_is_an_error() {
+    for traffic in [
+        "rate = 0.0",
+        "rate = -1.0",
+        "amount = \"uniform:1000:10\"",
+        "amount = \"uniform:0:10\"",
+        "amount = \"lognormal:9.0\"",
+        "amount = \"fixed:lots\"",
+    ] {
+        let invalid = format!("{}\n[traffic]\n{}", SLOT_JAM, traffic);
+        assert!(
+            matches!(Scenario::parse(&invalid), Err(LoadError::Parse(_))),
+            "{} accepted",
+            traffic
+        );
+    }
+    let valid = format!(
+        "{}\n[traffic]\nrate = 0.5\namount = \"uniform:10:1000\"",
+        SLOT_JAM
+    );
+    assert!(Scenario::parse(&valid).is_ok());
+}
+
+#[tokio::test]
+async fn slot_jam_runs_against_the_simulator() {
+    let scenario = Scenario::parse(SLOT_JAM).unwrap();
+    let (target, nodes) = network();
+    let results = scenario::run(
+        scenario,
+        nodes,
+        target,
+        None,
+        None,
+        TrafficStats::default(),
+        Timeline::start(),
+    )
+    .await;
+    assert_eq!(results.channels.len(), 2);
+    assert_eq!(results.jam.sent, 483);
+    assert_eq!(results.jam.held, 483);
+    assert!(results.passed);
+}
+
+#[tokio::test]
+async fn unfunded_setup_fails_the_scenario() {
+    let scenario =
+        Scenario::parse(&SLOT_JAM.replace("amount = 500000", "amount = 50000000")).unwrap();
+    let (target, nodes) = network();
+    let results = scenario::run(
+        scenario,
+        nodes,
+        target,
+        None,
+        None,
+        TrafficStats::default(),
+        Timeline::start(),
+    )
+    .await;
+    assert!(results.channels.is_empty());
+    assert_eq!(results.jam.sent, 0);
+    assert!(!results.passed);
+}
+
+#[tokio::test]
+async fn missing_target_peer_fails_the_scenario() {
+    let scenario = Scenario::parse(&SLOT_JAM.replace("peer_index = 2", "peer_index = 3")).unwrap();
+    let (target, nodes) = network();
+    let results = scenario::run(
+        scenario,
+        nodes,
+        target,
+        None,
+        None,
+        TrafficStats::default(),
+        Timeline::start(),
+    )
+    .await;
+    assert!(results.channels.is_empty());
+    assert_eq!(results.jam.sent, 0);
+    assert!(!results.passed);
+}
+
+#[tokio::test]
+async fn mission_control_left_by_an_unfinished_run_is_restored() {
+    let (target, nodes) = network();
+    let saved = MissionControlConfig {
+        half_life_seconds: 3600,
+        hop_probability: 0.6,
+        weight: 0.5,
+        maximum_payment_results: 1000,
+        minimum_failure_relax_interval: 60,
+    };
+    let path = std::env::temp_dir().join(format!("jammy-mc-{}.toml", std::process::id()));
+    let path = path.to_str().unwrap();
+    mission_control::save(
+        path,
+        &[(
+            String::from("alice"),
+            Snapshot {
+                pairs: Vec::new(),
+                config: saved.clone(),
+            },
+        )],
+    );
+    let mut alice = nodes["alice"].clone();
+    alice
+        .set_mission_control_config(MissionControlConfig {
+            hop_probability: 0.01,
+            ..saved.clone()
+        })
+        .await;
+
+    let mut scenario = Scenario::parse(SLOT_JAM).unwrap();
+    scenario.mission_control_file = Some(String::from(path));
+    scenario::run(
+        scenario,
+        nodes,
+        target,
+        None,
+        None,
+        TrafficStats::default(),
+        Timeline::start(),
+    )
+    .await;
+    assert_eq!(alice.mission_control_config().await, saved);
+    assert!(!std::path::Path::new(path).exists());
+}
@@ -28,7 +28,8 @@ fn main() -> std::io::Result<()> {
 
     tonic_build::configure()
         .build_client(true)
-        .build_server(false)
+        .build_server(std::env::var_os("CARGO_FEATURE_SERVER").is_some())
+        .generate_default_stubs(true)
         .compile(&proto_paths, &[dir])?;
     Ok(())
 }
@@ -0,0 +1,37 @@
+// This example starts a mock LND node, writing its certificate and macaroon to the given
+// directory, and serves it until killed. It prints the arguments to use with the other examples.
+//
+// Example run: `cargo run --features=server --example mock_lnd /tmp/mock_lnd`
+
+use fedimint_tonic_lnd::mock::MockLnd;
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args_os();
+    args.next().expect("not even zeroth arg given");
+    let dir = args.next().expect("missing argument: directory");
+
+    let mock = MockLnd::new();
+    mock.state().info.alias = String::from("mock");
+    let server = mock.serve(dir).await.expect("failed to start mock");
+
+    // Check that the mock is reachable like a real node would be
+    let info = server
+        .connect()
+        .await
+        .expect("failed to connect")
+        .lightning()
+        .get_info(fedimint_tonic_lnd::lnrpc::GetInfoRequest {})
+        .await
+        .expect("failed to get info")
+        .into_inner();
+    println!("serving {}", info.alias);
+    println!(
+        "{} {} {}",
+        server.address,
+        server.cert_file.display(),
+        server.macaroon_file.display()
+    );
+
+    std::future::pending::<()>().await;
+}
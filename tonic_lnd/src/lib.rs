@@ -126,6 +126,8 @@ impl Client {
 pub type Error = tonic::Status;
 
 mod error;
+#[cfg(feature = "server")]
+pub mod mock;
 
 macro_rules! try_map_err {
     ($result:expr, $mapfn:expr) => {
@@ -0,0 +1,519 @@
+//! Scriptable mock of the LND Lightning, Router and Invoices services
+//!
+//! The mock serves canned responses taken from [`MockState`], which can be changed at any time,
+//! and keeps track of the invoices and channels created through it so that simple flows (open a
+//! channel, add a hold invoice, settle it) behave like they would against a real node. Services
+//! and methods that are not mocked answer with `Unimplemented`.
+//!
+//! It is served over TLS with a freshly generated self-signed certificate and macaroon, so
+//! [`connect`](crate::connect) can be used against it unchanged.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::{TcpListenerStream, UnboundedReceiverStream};
+use tokio_stream::StreamExt;
+use tonic::codegen::BoxStream;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::invoicesrpc::invoices_server::{Invoices, InvoicesServer};
+use crate::lnrpc::invoice::InvoiceState;
+use crate::lnrpc::lightning_server::{Lightning, LightningServer};
+use crate::lnrpc::payment::PaymentStatus;
+use crate::routerrpc::router_server::{Router, RouterServer};
+use crate::{invoicesrpc, lnrpc, routerrpc, Client, ConnectError};
+
+/// Responses served by a [`MockLnd`]
+///
+/// The invoice and channel fields are also updated by the calls the mock serves.
+#[derive(Default)]
+pub struct MockState {
+    /// Returned by `GetInfo`.
+    pub info: lnrpc::GetInfoResponse,
+    /// Returned by `GetNodeInfo`, keyed by hex encoded pubkey.
+    pub nodes: HashMap<String, lnrpc::NodeInfo>,
+    /// Returned by `ListChannels`. `OpenChannelSync` appends to it.
+    pub channels: Vec<lnrpc::Channel>,
+    /// Returned by `PendingChannels`.
+    pub pending_channels: lnrpc::PendingChannelsResponse,
+    /// Returned by `ListPeers`. `ConnectPeer` appends to it.
+    pub peers: Vec<lnrpc::Peer>,
+    /// Invoices keyed by payment hash. `AddInvoice` and `AddHoldInvoice` insert into it.
+    pub invoices: HashMap<Vec<u8>, lnrpc::Invoice>,
+    /// Updates streamed for every `SendPaymentV2` call. If empty, a single `SUCCEEDED` update is
+    /// sent.
+    pub payment_updates: Vec<lnrpc::Payment>,
+    /// Streamed to every `SubscribeChannelGraph` subscriber.
+    pub graph_updates: Vec<lnrpc::GraphTopologyUpdate>,
+    /// Streamed to every `SubscribeHtlcEvents` subscriber.
+    pub htlc_events: Vec<routerrpc::HtlcEvent>,
+    /// Handed to every `HtlcInterceptor` client as soon as it registers.
+    pub intercepted_htlcs: Vec<routerrpc::ForwardHtlcInterceptRequest>,
+    /// Resolutions sent back by `HtlcInterceptor` clients, in order.
+    pub intercept_responses: Vec<routerrpc::ForwardHtlcInterceptResponse>,
+    /// Names of the methods called so far, in order.
+    pub calls: Vec<&'static str>,
+    invoice_subscribers: Vec<mpsc::UnboundedSender<lnrpc::Invoice>>,
+}
+
+impl MockState {
+    fn notify(&mut self, invoice: &lnrpc::Invoice) {
+        self.invoice_subscribers
+            .retain(|subscriber| subscriber.send(invoice.clone()).is_ok());
+    }
+
+    fn insert_invoice(&mut self, invoice: lnrpc::Invoice) -> lnrpc::Invoice {
+        let mut invoice = invoice;
+        invoice.add_index = self.invoices.len() as u64 + 1;
+        invoice.payment_request = format!("lnmock{}", hex::encode(&invoice.r_hash));
+        invoice.payment_addr = random_bytes();
+        if invoice.value_msat == 0 {
+            invoice.value_msat = invoice.value * 1000;
+        }
+        self.notify(&invoice);
+        self.invoices
+            .insert(invoice.r_hash.clone(), invoice.clone());
+        invoice
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn resolve_invoice(
+        &mut self,
+        hash: &[u8],
+        state: InvoiceState,
+        preimage: Vec<u8>,
+    ) -> Result<(), Status> {
+        let settle_index = self
+            .invoices
+            .values()
+            .filter(|invoice| invoice.state == InvoiceState::Settled as i32)
+            .count() as u64
+            + 1;
+        let invoice = self
+            .invoices
+            .get_mut(hash)
+            .ok_or_else(|| Status::not_found("unable to locate invoice"))?;
+        if invoice.state == InvoiceState::Settled as i32
+            || invoice.state == InvoiceState::Canceled as i32
+        {
+            return Err(Status::failed_precondition("invoice already resolved"));
+        }
+        invoice.state = state as i32;
+        if state == InvoiceState::Settled {
+            invoice.r_preimage = preimage;
+            invoice.settle_index = settle_index;
+            invoice.amt_paid_sat = invoice.value;
+            invoice.amt_paid_msat = invoice.value_msat;
+        }
+        let invoice = invoice.clone();
+        self.notify(&invoice);
+        Ok(())
+    }
+}
+
+/// Mock LND node, see the [module documentation](self)
+#[derive(Clone, Default)]
+pub struct MockLnd {
+    state: Arc<Mutex<MockState>>,
+}
+
+/// A running [`MockLnd`], stopped when dropped
+pub struct MockServer {
+    /// Address to pass to [`connect`](crate::connect).
+    pub address: String,
+    /// PEM encoded self-signed certificate the server presents.
+    pub cert_file: PathBuf,
+    /// Macaroon the server requires.
+    pub macaroon_file: PathBuf,
+    handle: JoinHandle<Result<(), tonic::transport::Error>>,
+}
+
+impl MockServer {
+    /// Connects a client to the mock.
+    pub async fn connect(&self) -> Result<Client, ConnectError> {
+        crate::connect(
+            self.address.clone(),
+            self.cert_file.clone(),
+            self.macaroon_file.clone(),
+        )
+        .await
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn random_bytes() -> Vec<u8> {
+    let mut bytes = vec![0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system randomness unavailable");
+    bytes
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, data)
+        .as_ref()
+        .to_vec()
+}
+
+/// Canned items followed by a stream that never ends, like LND's subscriptions.
+fn canned<T: Send + 'static>(items: Vec<T>) -> BoxStream<T> {
+    Box::pin(tokio_stream::iter(items.into_iter().map(Ok)).chain(tokio_stream::pending()))
+}
+
+fn other_error(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
+
+impl MockLnd {
+    /// Creates a mock with default (empty) responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the state to inspect or script responses.
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock state poisoned")
+    }
+
+    fn call(&self, method: &'static str) -> MutexGuard<'_, MockState> {
+        let mut state = self.state();
+        state.calls.push(method);
+        state
+    }
+
+    /// Starts serving on a random local port.
+    ///
+    /// The certificate and macaroon are written to `dir` as `tls.cert` and `admin.macaroon`.
+    #[allow(clippy::result_large_err)]
+    pub async fn serve(&self, dir: impl AsRef<Path>) -> io::Result<MockServer> {
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+
+        let cert = rcgen::generate_simple_self_signed(vec![
+            String::from("localhost"),
+            String::from("127.0.0.1"),
+        ])
+        .map_err(other_error)?;
+        let cert_pem = cert.serialize_pem().map_err(other_error)?;
+        let key_pem = cert.serialize_private_key_pem();
+        let cert_file = dir.join("tls.cert");
+        tokio::fs::write(&cert_file, &cert_pem).await?;
+
+        let macaroon = random_bytes();
+        let macaroon_file = dir.join("admin.macaroon");
+        tokio::fs::write(&macaroon_file, &macaroon).await?;
+        let macaroon = hex::encode(macaroon);
+        let check_macaroon = move |request: Request<()>| match request
+            .metadata()
+            .get("macaroon")
+            .map(|m| m.to_str())
+        {
+            Some(Ok(m)) if m == macaroon => Ok(request),
+            _ => Err(Status::unauthenticated("verification failed")),
+        };
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("https://{}", listener.local_addr()?);
+        let router = Server::builder()
+            .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(cert_pem, key_pem)))
+            .map_err(other_error)?
+            .add_service(LightningServer::with_interceptor(
+                self.clone(),
+                check_macaroon.clone(),
+            ))
+            .add_service(RouterServer::with_interceptor(
+                self.clone(),
+                check_macaroon.clone(),
+            ))
+            .add_service(InvoicesServer::with_interceptor(
+                self.clone(),
+                check_macaroon,
+            ));
+        let handle = tokio::spawn(router.serve_with_incoming(TcpListenerStream::new(listener)));
+
+        Ok(MockServer {
+            address,
+            cert_file,
+            macaroon_file,
+            handle,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Lightning for MockLnd {
+    async fn get_info(
+        &self,
+        _request: Request<lnrpc::GetInfoRequest>,
+    ) -> Result<Response<lnrpc::GetInfoResponse>, Status> {
+        Ok(Response::new(self.call("GetInfo").info.clone()))
+    }
+
+    async fn get_node_info(
+        &self,
+        request: Request<lnrpc::NodeInfoRequest>,
+    ) -> Result<Response<lnrpc::NodeInfo>, Status> {
+        self.call("GetNodeInfo")
+            .nodes
+            .get(&request.into_inner().pub_key)
+            .cloned()
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found("unable to find node"))
+    }
+
+    async fn list_channels(
+        &self,
+        request: Request<lnrpc::ListChannelsRequest>,
+    ) -> Result<Response<lnrpc::ListChannelsResponse>, Status> {
+        let peer = hex::encode(request.into_inner().peer);
+        let channels = self
+            .call("ListChannels")
+            .channels
+            .iter()
+            .filter(|channel| peer.is_empty() || channel.remote_pubkey == peer)
+            .cloned()
+            .collect();
+        Ok(Response::new(lnrpc::ListChannelsResponse { channels }))
+    }
+
+    async fn pending_channels(
+        &self,
+        _request: Request<lnrpc::PendingChannelsRequest>,
+    ) -> Result<Response<lnrpc::PendingChannelsResponse>, Status> {
+        Ok(Response::new(
+            self.call("PendingChannels").pending_channels.clone(),
+        ))
+    }
+
+    async fn open_channel_sync(
+        &self,
+        request: Request<lnrpc::OpenChannelRequest>,
+    ) -> Result<Response<lnrpc::ChannelPoint>, Status> {
+        let request = request.into_inner();
+        let mut state = self.call("OpenChannelSync");
+        let txid = random_bytes();
+        let chan_id = state.channels.len() as u64 + 1;
+        let mut display_txid = txid.clone();
+        display_txid.reverse();
+        state.channels.push(lnrpc::Channel {
+            active: true,
+            remote_pubkey: hex::encode(&request.node_pubkey),
+            channel_point: format!("{}:0", hex::encode(display_txid)),
+            chan_id,
+            capacity: request.local_funding_amount,
+            local_balance: request.local_funding_amount - request.push_sat,
+            remote_balance: request.push_sat,
+            initiator: true,
+            ..Default::default()
+        });
+        Ok(Response::new(lnrpc::ChannelPoint {
+            funding_txid: Some(lnrpc::channel_point::FundingTxid::FundingTxidBytes(txid)),
+            output_index: 0,
+        }))
+    }
+
+    async fn connect_peer(
+        &self,
+        request: Request<lnrpc::ConnectPeerRequest>,
+    ) -> Result<Response<lnrpc::ConnectPeerResponse>, Status> {
+        let addr = request.into_inner().addr.unwrap_or_default();
+        let mut state = self.call("ConnectPeer");
+        if state.peers.iter().any(|peer| peer.pub_key == addr.pubkey) {
+            return Err(Status::unknown(format!(
+                "already connected to peer: {}@{}",
+                addr.pubkey, addr.host
+            )));
+        }
+        state.peers.push(lnrpc::Peer {
+            pub_key: addr.pubkey,
+            address: addr.host,
+            ..Default::default()
+        });
+        Ok(Response::new(lnrpc::ConnectPeerResponse::default()))
+    }
+
+    async fn list_peers(
+        &self,
+        _request: Request<lnrpc::ListPeersRequest>,
+    ) -> Result<Response<lnrpc::ListPeersResponse>, Status> {
+        Ok(Response::new(lnrpc::ListPeersResponse {
+            peers: self.call("ListPeers").peers.clone(),
+        }))
+    }
+
+    async fn new_address(
+        &self,
+        _request: Request<lnrpc::NewAddressRequest>,
+    ) -> Result<Response<lnrpc::NewAddressResponse>, Status> {
+        drop(self.call("NewAddress"));
+        Ok(Response::new(lnrpc::NewAddressResponse {
+            address: format!("bcrt1q{}", hex::encode(&random_bytes()[..20])),
+        }))
+    }
+
+    async fn add_invoice(
+        &self,
+        request: Request<lnrpc::Invoice>,
+    ) -> Result<Response<lnrpc::AddInvoiceResponse>, Status> {
+        let mut invoice = request.into_inner();
+        if invoice.r_preimage.is_empty() {
+            invoice.r_preimage = random_bytes();
+        }
+        invoice.r_hash = sha256(&invoice.r_preimage);
+        let invoice = self.call("AddInvoice").insert_invoice(invoice);
+        Ok(Response::new(lnrpc::AddInvoiceResponse {
+            r_hash: invoice.r_hash,
+            payment_request: invoice.payment_request,
+            add_index: invoice.add_index,
+            payment_addr: invoice.payment_addr,
+        }))
+    }
+
+    async fn lookup_invoice(
+        &self,
+        request: Request<lnrpc::PaymentHash>,
+    ) -> Result<Response<lnrpc::Invoice>, Status> {
+        self.call("LookupInvoice")
+            .invoices
+            .get(&request.into_inner().r_hash)
+            .cloned()
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found("unable to locate invoice"))
+    }
+
+    async fn subscribe_invoices(
+        &self,
+        _request: Request<lnrpc::InvoiceSubscription>,
+    ) -> Result<Response<BoxStream<lnrpc::Invoice>>, Status> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.call("SubscribeInvoices").invoice_subscribers.push(tx);
+        Ok(Response::new(Box::pin(
+            UnboundedReceiverStream::new(rx).map(Ok),
+        )))
+    }
+
+    async fn subscribe_channel_graph(
+        &self,
+        _request: Request<lnrpc::GraphTopologySubscription>,
+    ) -> Result<Response<BoxStream<lnrpc::GraphTopologyUpdate>>, Status> {
+        let updates = self.call("SubscribeChannelGraph").graph_updates.clone();
+        Ok(Response::new(canned(updates)))
+    }
+}
+
+#[tonic::async_trait]
+impl Router for MockLnd {
+    async fn send_payment_v2(
+        &self,
+        request: Request<routerrpc::SendPaymentRequest>,
+    ) -> Result<Response<BoxStream<lnrpc::Payment>>, Status> {
+        let request = request.into_inner();
+        let mut updates = self.call("SendPaymentV2").payment_updates.clone();
+        if updates.is_empty() {
+            updates.push(lnrpc::Payment {
+                status: PaymentStatus::Succeeded as i32,
+                ..Default::default()
+            });
+        }
+        let payment_hash = request
+            .payment_request
+            .strip_prefix("lnmock")
+            .map(String::from)
+            .unwrap_or_else(|| hex::encode(&request.payment_hash));
+        for update in updates.iter_mut() {
+            update.payment_request = request.payment_request.clone();
+            update.payment_hash = payment_hash.clone();
+        }
+        Ok(Response::new(Box::pin(tokio_stream::iter(
+            updates.into_iter().map(Ok),
+        ))))
+    }
+
+    async fn subscribe_htlc_events(
+        &self,
+        _request: Request<routerrpc::SubscribeHtlcEventsRequest>,
+    ) -> Result<Response<BoxStream<routerrpc::HtlcEvent>>, Status> {
+        let events = self.call("SubscribeHtlcEvents").htlc_events.clone();
+        Ok(Response::new(canned(events)))
+    }
+
+    async fn htlc_interceptor(
+        &self,
+        request: Request<Streaming<routerrpc::ForwardHtlcInterceptResponse>>,
+    ) -> Result<Response<BoxStream<routerrpc::ForwardHtlcInterceptRequest>>, Status> {
+        let htlcs = self.call("HtlcInterceptor").intercepted_htlcs.clone();
+        let mut responses = request.into_inner();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(response)) = responses.message().await {
+                state
+                    .lock()
+                    .expect("mock state poisoned")
+                    .intercept_responses
+                    .push(response);
+            }
+        });
+        Ok(Response::new(canned(htlcs)))
+    }
+}
+
+#[tonic::async_trait]
+impl Invoices for MockLnd {
+    async fn add_hold_invoice(
+        &self,
+        request: Request<invoicesrpc::AddHoldInvoiceRequest>,
+    ) -> Result<Response<invoicesrpc::AddHoldInvoiceResp>, Status> {
+        let request = request.into_inner();
+        let invoice = self.call("AddHoldInvoice").insert_invoice(lnrpc::Invoice {
+            memo: request.memo,
+            r_hash: request.hash,
+            value: request.value,
+            value_msat: request.value_msat,
+            expiry: request.expiry,
+            cltv_expiry: request.cltv_expiry,
+            private: request.private,
+            ..Default::default()
+        });
+        Ok(Response::new(invoicesrpc::AddHoldInvoiceResp {
+            payment_request: invoice.payment_request,
+            add_index: invoice.add_index,
+            payment_addr: invoice.payment_addr,
+        }))
+    }
+
+    async fn settle_invoice(
+        &self,
+        request: Request<invoicesrpc::SettleInvoiceMsg>,
+    ) -> Result<Response<invoicesrpc::SettleInvoiceResp>, Status> {
+        let preimage = request.into_inner().preimage;
+        self.call("SettleInvoice").resolve_invoice(
+            &sha256(&preimage),
+            InvoiceState::Settled,
+            preimage,
+        )?;
+        Ok(Response::new(invoicesrpc::SettleInvoiceResp {}))
+    }
+
+    async fn cancel_invoice(
+        &self,
+        request: Request<invoicesrpc::CancelInvoiceMsg>,
+    ) -> Result<Response<invoicesrpc::CancelInvoiceResp>, Status> {
+        self.call("CancelInvoice").resolve_invoice(
+            &request.into_inner().payment_hash,
+            InvoiceState::Canceled,
+            Vec::new(),
+        )?;
+        Ok(Response::new(invoicesrpc::CancelInvoiceResp {}))
+    }
+}
@@ -0,0 +1,107 @@
+//! Runs the calls of the examples against [`MockLnd`].
+
+#![cfg(feature = "server")]
+
+use fedimint_tonic_lnd::invoicesrpc::{AddHoldInvoiceRequest, CancelInvoiceMsg};
+use fedimint_tonic_lnd::lnrpc::invoice::InvoiceState;
+use fedimint_tonic_lnd::lnrpc::{GetInfoRequest, InvoiceSubscription};
+use fedimint_tonic_lnd::mock::{MockLnd, MockServer};
+use fedimint_tonic_lnd::routerrpc::{
+    CircuitKey, ForwardHtlcInterceptRequest, ForwardHtlcInterceptResponse, ResolveHoldForwardAction,
+};
+use fedimint_tonic_lnd::Client;
+
+async fn start(name: &str) -> (MockLnd, MockServer, Client) {
+    let mock = MockLnd::new();
+    let dir = std::env::temp_dir().join(format!("tonic-lnd-mock-{}-{}", name, std::process::id()));
+    let server = mock.serve(dir).await.expect("failed to start mock");
+    let client = server.connect().await.expect("failed to connect");
+    (mock, server, client)
+}
+
+#[tokio::test]
+async fn get_info() {
+    let (mock, _server, mut client) = start("get_info").await;
+    mock.state().info.alias = String::from("mock");
+    let info = client
+        .lightning()
+        .get_info(GetInfoRequest {})
+        .await
+        .expect("failed to get info")
+        .into_inner();
+    assert_eq!(info.alias, "mock");
+}
+
+#[tokio::test]
+async fn cancelled_hold_invoice_is_streamed() {
+    let (_mock, _server, mut client) = start("cancel_invoice").await;
+    let mut invoices = client
+        .lightning()
+        .subscribe_invoices(InvoiceSubscription::default())
+        .await
+        .expect("failed to subscribe")
+        .into_inner();
+    let hash = vec![7; 32];
+    client
+        .invoices()
+        .add_hold_invoice(AddHoldInvoiceRequest {
+            hash: hash.clone(),
+            value: 1000,
+            ..Default::default()
+        })
+        .await
+        .expect("failed to add invoice");
+    client
+        .invoices()
+        .cancel_invoice(CancelInvoiceMsg {
+            payment_hash: hash.clone(),
+        })
+        .await
+        .expect("failed to cancel invoice");
+
+    let added = invoices.message().await.unwrap().unwrap();
+    assert_eq!(added.state, InvoiceState::Open as i32);
+    let cancelled = invoices.message().await.unwrap().unwrap();
+    assert_eq!(cancelled.r_hash, hash);
+    assert_eq!(cancelled.state, InvoiceState::Canceled as i32);
+}
+
+#[tokio::test]
+async fn intercepted_htlcs_are_resumed() {
+    let (mock, _server, mut client) = start("intercept_htlcs").await;
+    let key = CircuitKey {
+        chan_id: 1,
+        htlc_id: 2,
+    };
+    mock.state()
+        .intercepted_htlcs
+        .push(ForwardHtlcInterceptRequest {
+            incoming_circuit_key: Some(key.clone()),
+            ..Default::default()
+        });
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let mut htlcs = client
+        .router()
+        .htlc_interceptor(tokio_stream::wrappers::ReceiverStream::new(rx))
+        .await
+        .expect("failed to intercept")
+        .into_inner();
+    let htlc = htlcs.message().await.unwrap().unwrap();
+    tx.send(ForwardHtlcInterceptResponse {
+        incoming_circuit_key: htlc.incoming_circuit_key,
+        action: ResolveHoldForwardAction::Resume as i32,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    for _ in 0..100 {
+        if !mock.state().intercept_responses.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let responses = mock.state().intercept_responses.clone();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].incoming_circuit_key, Some(key));
+}